@@ -1,6 +1,6 @@
 // Copyright (C) 2021 lifning, licensed under the GNU Affero General Public License version 3.
 
-use flowergal_proj_config::resources::{RoomData, TextScreenblockEntry, TilePatterns, ROOM_SIZE};
+use flowergal_proj_config::resources::{RoomData, RoomEntries4bpp, TextScreenblockEntry, TilePatterns, ROOM_SIZE};
 use std::error::Error;
 use std::mem::size_of;
 use std::slice::from_raw_parts;
@@ -41,31 +41,40 @@ impl CompressibleAsset for TilePatterns {
     }
 }
 
+/// each room padded out to the screenblock's width, so it can be decompressed straight into one
+fn compress_text_rooms(rooms: &[RoomEntries4bpp]) -> Result<&'static [&'static [u32]], Box<dyn Error>> {
+    let mut compressed_rooms = Vec::<&'static [u32]>::new();
+    for room in rooms {
+        let mut padded = Vec::new();
+        for (i, row) in room.0.iter().enumerate() {
+            for entry in row.iter() {
+                padded.push(*entry);
+            }
+            if i < ROOM_SIZE.1 - 1 {
+                for _ in (ROOM_SIZE.0)..32 {
+                    padded.push(TextScreenblockEntry::new());
+                }
+            }
+        }
+        compressed_rooms.push(do_lz77_compression(&padded, true)?);
+    }
+    Ok(Box::leak(compressed_rooms.into_boxed_slice()))
+}
+
 impl CompressibleAsset for RoomData {
     fn compress(self) -> Result<Self, Box<dyn Error>> {
         match self {
-            RoomData::Text(rooms) => {
+            RoomData::Text(rooms) => Ok(RoomData::TextLz77(compress_text_rooms(rooms)?)),
+            RoomData::Text8bpp(rooms) => Ok(RoomData::Text8bppLz77(compress_text_rooms(rooms)?)),
+            RoomData::Affine(rooms) => {
+                // a room is exactly one affine screenblock, so no padding.  vram_safe keeps
+                // the BIOS from needing to read back a byte it hasn't written as a halfword yet.
                 let mut compressed_rooms = Vec::<&'static [u32]>::new();
                 for room in rooms {
-                    let mut padded = Vec::new();
-                    for (i, row) in room.0.iter().enumerate() {
-                        for entry in row.iter() {
-                            padded.push(*entry);
-                        }
-                        if i < ROOM_SIZE.1 - 1 {
-                            for _ in (ROOM_SIZE.0)..32 {
-                                padded.push(TextScreenblockEntry::new());
-                            }
-                        }
-                    }
-                    compressed_rooms.push(do_lz77_compression(&padded, true)?);
+                    compressed_rooms.push(do_lz77_compression(&room.0[..], true)?);
                 }
-                Ok(RoomData::TextLz77(Box::leak(
-                    compressed_rooms.into_boxed_slice(),
-                )))
+                Ok(RoomData::AffineLz77(Box::leak(compressed_rooms.into_boxed_slice())))
             }
-            // TODO: support.  for now, passthrough is OK
-            //RoomData::Affine(_) => Err("compressing affine RoomData not yet supported".into()),
             x => Ok(x),
         }
     }
@@ -124,13 +124,8 @@ fn get_relevant_crate_licenses() -> Result<BTreeMap<String, BTreeMap<&'static st
     crates.insert("flac-demo".to_string(), KrateConfig {
         additional: vec![],
         ignore: [
-            ("BSD-3-Clause", "external/flac/COPYING.Xiph"),
-            ("GFDL-1.2", "external/flac/COPYING.FDL"),
-            ("GPL-2.0", "external/flac/COPYING.GPL"),
-            ("LGPL-2.1", "external/flac/COPYING.LGPL"),
             ("Apache-2.0", "external/gba/LICENSE-APACHE2.txt"),
             ("MIT", "external/gba-compression/LICENSE"),
-            ("GPL-3.0", "external/lossywav/gpl.txt"),
             ("BSD-3-Clause", "internal/flowergal-runtime/COPYING"),
             ("MIT", "internal/flowergal-runtime/COPYING-simpleflac"),
             ("AGPL-3.0", "internal/flowergal-buildtools/COPYING"),
@@ -1,7 +1,50 @@
 extern crate flowergal_buildtools;
-//use flowergal_buildtools::*;
+use flowergal_buildtools::music::gba_output::{render_to_wav, OutputSettings};
 use std::error::Error;
 
+const USAGE: &str = "usage: flowergal-buildtools simulate <input.flac|input.pcm8> <output.wav> \
+[--bias 0x200] [--resolution 0-3] [--half-volume-a] [--half-volume-b]";
+
+fn parse_number(s: &str) -> Result<u16, Box<dyn Error>> {
+    Ok(match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16)?,
+        None => s.parse()?,
+    })
+}
+
+fn simulate(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut settings = OutputSettings::default();
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bias" => {
+                settings.bias = parse_number(args.next().ok_or(USAGE)?)?;
+                if settings.bias > 0x3FF {
+                    return Err("bias is 10 bits".into());
+                }
+            }
+            "--resolution" => {
+                settings.amplitude_resolution = parse_number(args.next().ok_or(USAGE)?)? as u8;
+                if settings.amplitude_resolution > 3 {
+                    return Err("resolution is 0 (9-bit) through 3 (6-bit)".into());
+                }
+            }
+            "--half-volume-a" => settings.dma_a_full_volume = false,
+            "--half-volume-b" => settings.dma_b_full_volume = false,
+            _ => paths.push(arg),
+        }
+    }
+    match paths.as_slice() {
+        [input, output] => render_to_wav(input, output, &settings),
+        _ => Err(USAGE.into()),
+    }
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
-    Ok(())
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(|s| s.as_str()) {
+        Some("simulate") => simulate(&args[1..]),
+        _ => Err(USAGE.into()),
+    }
 }
@@ -0,0 +1,215 @@
+// Copyright (C) 2021 lifning, licensed under the GNU Affero General Public License version 3.
+
+//! Adaptive, noise-shaped bit depth reduction, in the spirit of lossyWAV: for each block, find
+//! the quietest part of the spectrum and throw away as many low bits as we can while keeping
+//! the (shaped) quantization noise below it.  Blocks line up with FLAC frames, so the zeroed
+//! bits turn into "wasted bits" that the encoder gets for free.
+
+use std::f64::consts::PI;
+use std::ops::Range;
+
+use flowergal_proj_config::sound_info::{MIXER_BITS, PLAYBUF_SIZE};
+
+use crate::music::flac_encoder::SAMPLE_DEPTH;
+use crate::music::manifest::QualityPreset;
+
+/// the mixer only ever outputs the top MIXER_BITS of each sample, so anything below that is
+/// going to get truncated at runtime anyway.  better we round it off here, with shaping.
+pub const MIN_BITS_REMOVED: u32 = SAMPLE_DEPTH - MIXER_BITS;
+
+/// analysis FFT sizes: a short one for transients, a long one for frequency resolution
+const FFT_SIZES: &[usize] = &[64, 256];
+
+/// error feedback filter, `e[n-1]` first.  gives a noise transfer function of
+/// `1 - 0.7z^-1 + 0.2z^-2`, ~6dB down at DC and ~5.6dB up at nyquist.  gentle on purpose:
+/// pushing noise too far up runs into the treble, which is where most tracks are quietest.
+const NOISE_SHAPING: [f64; 2] = [0.7, -0.2];
+
+#[derive(Clone, Debug)]
+pub struct BitReductionSettings {
+    pub min_bits_removed: u32,
+    pub max_bits_removed: u32,
+    /// how far the shaped noise must stay below the quietest (smoothed) spectral bin, in dB
+    pub noise_margin_db: f64,
+}
+
+impl From<QualityPreset> for BitReductionSettings {
+    fn from(preset: QualityPreset) -> Self {
+        let (max_bits_removed, noise_margin_db) = match preset {
+            QualityPreset::High => (9, 12.0),
+            QualityPreset::Standard => (10, 6.0),
+            QualityPreset::Portable => (11, 0.0),
+        };
+        BitReductionSettings {
+            min_bits_removed: MIN_BITS_REMOVED,
+            max_bits_removed,
+            noise_margin_db,
+        }
+    }
+}
+
+pub struct BitReductionResult {
+    pub samples: Vec<i16>,
+    /// how many low bits were zeroed in each PLAYBUF_SIZE block
+    pub bits_removed: Vec<u32>,
+}
+
+pub fn reduce_bit_depth(samples: &[i16], settings: &BitReductionSettings) -> BitReductionResult {
+    let analyzers: Vec<SpectrumAnalyzer> =
+        FFT_SIZES.iter().map(|n| SpectrumAnalyzer::new(*n)).collect();
+
+    let mut output = Vec::with_capacity(samples.len());
+    let mut bits_removed = Vec::with_capacity(samples.len() / PLAYBUF_SIZE + 1);
+    let mut error_history = [0.0f64; NOISE_SHAPING.len()];
+
+    for (block_index, block) in samples.chunks(PLAYBUF_SIZE).enumerate() {
+        let start = block_index * PLAYBUF_SIZE;
+        let allowed = analyzers
+            .iter()
+            .map(|a| {
+                a.max_bits_removable(samples, start..start + block.len(), settings.noise_margin_db)
+            })
+            .fold(f64::INFINITY, f64::min);
+        let bits = if allowed.is_finite() {
+            (allowed.floor().max(0.0) as u32)
+                .max(settings.min_bits_removed)
+                .min(settings.max_bits_removed)
+        } else {
+            settings.min_bits_removed
+        };
+        bits_removed.push(bits);
+
+        let step = (1i32 << bits) as f64;
+        let max_value = (i16::MAX as i32 + 1 - (1 << bits)) as f64;
+        let min_value = i16::MIN as f64;
+        for x in block {
+            let shaped_error: f64 = NOISE_SHAPING
+                .iter()
+                .zip(error_history.iter())
+                .map(|(c, e)| c * e)
+                .sum();
+            let wanted = *x as f64 - shaped_error;
+            let quantized = ((wanted / step).round() * step).max(min_value).min(max_value);
+            // clipping can make the error arbitrarily large; don't let it ring forever
+            let error = (quantized - wanted).max(-step).min(step);
+            for i in (1..error_history.len()).rev() {
+                error_history[i] = error_history[i - 1];
+            }
+            error_history[0] = error;
+            output.push(quantized as i16);
+        }
+    }
+
+    BitReductionResult {
+        samples: output,
+        bits_removed,
+    }
+}
+
+struct SpectrumAnalyzer {
+    size: usize,
+    window: Vec<f64>,
+    /// sum of squared window coefficients, i.e. the gain white noise gets in each bin
+    window_power: f64,
+    /// |NTF|^2 of the noise shaping filter at each bin's center frequency
+    shaping_power: Vec<f64>,
+}
+
+impl SpectrumAnalyzer {
+    fn new(size: usize) -> Self {
+        let window: Vec<f64> = (0..size)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * (i as f64 + 0.5) / size as f64).cos())
+            .collect();
+        let window_power = window.iter().map(|w| w * w).sum();
+        let shaping_power = (0..=size / 2)
+            .map(|k| {
+                let omega = 2.0 * PI * k as f64 / size as f64;
+                let (mut re, mut im) = (1.0, 0.0);
+                for (j, c) in NOISE_SHAPING.iter().enumerate() {
+                    let phase = omega * (j + 1) as f64;
+                    re -= c * phase.cos();
+                    im += c * phase.sin();
+                }
+                re * re + im * im
+            })
+            .collect();
+        SpectrumAnalyzer {
+            size,
+            window,
+            window_power,
+            shaping_power,
+        }
+    }
+
+    /// the most bits we could drop from `range` of `samples` while keeping shaped quantization
+    /// noise `margin_db` below every smoothed bin of every analysis window overlapping it.
+    fn max_bits_removable(&self, samples: &[i16], range: Range<usize>, margin_db: f64) -> f64 {
+        let hop = self.size / 2;
+        let margin = 10f64.powf(margin_db / 10.0);
+        let first = range.start.saturating_sub(hop);
+        let mut result = f64::INFINITY;
+
+        let mut window_start = first;
+        while window_start < range.end && window_start + self.size <= samples.len() {
+            let mut re: Vec<f64> = samples[window_start..window_start + self.size]
+                .iter()
+                .zip(self.window.iter())
+                .map(|(x, w)| *x as f64 * w)
+                .collect();
+            let mut im = vec![0.0; self.size];
+            fft(&mut re, &mut im);
+            let power: Vec<f64> = re.iter().zip(im.iter()).map(|(r, i)| r * r + i * i).collect();
+
+            // skip DC and nyquist, and smooth over neighboring bins so a single notch in the
+            // spectrum doesn't veto the whole block
+            for k in 1..self.size / 2 {
+                let smoothed = (power[k - 1] + power[k] + power[k + 1]) / 3.0;
+                let noise_per_lsb = self.window_power * self.shaping_power[k] / 12.0;
+                // noise power for removing b bits is 4^b times that of removing one LSB's worth
+                let ratio = smoothed / (noise_per_lsb * margin);
+                let bits = if ratio > 0.0 { 0.5 * ratio.log2() } else { 0.0 };
+                result = result.min(bits);
+            }
+            window_start += hop;
+        }
+
+        result
+    }
+}
+
+/// in-place iterative radix-2 FFT.  `re.len()` must be a power of two.
+fn fft(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_im, w_re) = (angle * k as f64).sin_cos();
+                let a = start + k;
+                let b = a + len / 2;
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
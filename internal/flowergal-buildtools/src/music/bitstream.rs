@@ -0,0 +1,191 @@
+// Copyright (C) 2021 lifning, licensed under the GNU Affero General Public License version 3.
+
+/// MSB-first bit writer, matching the bit order FLAC (and thus `SimpleFlac`) reads in.
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    acc_len: u32,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        BitWriter {
+            bytes: Vec::new(),
+            acc: 0,
+            acc_len: 0,
+        }
+    }
+
+    pub fn write(&mut self, value: u64, bits: u32) {
+        assert!(bits <= 32);
+        if bits == 0 {
+            return;
+        }
+        let masked = value & ((1u64 << bits) - 1);
+        self.acc = (self.acc << bits) | masked;
+        self.acc_len += bits;
+        while self.acc_len >= 8 {
+            self.acc_len -= 8;
+            self.bytes.push((self.acc >> self.acc_len) as u8);
+        }
+        self.acc &= (1u64 << self.acc_len) - 1;
+    }
+
+    /// two's complement, truncated to `bits`
+    pub fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64, bits);
+    }
+
+    /// `count` zeroes followed by a one
+    pub fn write_unary(&mut self, count: u32) {
+        let mut remaining = count;
+        while remaining >= 32 {
+            self.write(0, 32);
+            remaining -= 32;
+        }
+        self.write(1, remaining + 1);
+    }
+
+    /// zigzag-folded Rice code, as read by `SimpleFlac::read_rice_signed_int`
+    pub fn write_rice_signed(&mut self, value: i32, param: u32) {
+        let folded = zigzag(value);
+        self.write_unary(folded >> param);
+        self.write(folded as u64, param);
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.write(*b as u64, 8);
+        }
+    }
+
+    pub fn align_to_byte(&mut self) {
+        if self.acc_len != 0 {
+            self.write(0, 8 - self.acc_len);
+        }
+    }
+
+    /// only the whole bytes written so far
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(mut self) -> Vec<u8> {
+        self.align_to_byte();
+        self.bytes
+    }
+}
+
+pub fn zigzag(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)) as u32
+}
+
+/// MSB-first bit reader over a byte slice, for inspecting what we (or anyone else) encoded.
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    bit_position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        BitReader {
+            data,
+            bit_position: 0,
+        }
+    }
+
+    pub fn bit_position(&self) -> usize {
+        self.bit_position
+    }
+
+    pub fn byte_position(&self) -> usize {
+        self.bit_position / 8
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bit_position >= self.data.len() * 8
+    }
+
+    pub fn read(&mut self, bits: u32) -> Result<u64, String> {
+        assert!(bits <= 64);
+        if self.bit_position + bits as usize > self.data.len() * 8 {
+            return Err(format!(
+                "unexpected end of stream reading {} bits at byte {}",
+                bits,
+                self.byte_position()
+            ));
+        }
+        let mut result = 0u64;
+        for _ in 0..bits {
+            let byte = self.data[self.bit_position / 8];
+            let bit = (byte >> (7 - (self.bit_position & 7))) & 1;
+            result = (result << 1) | bit as u64;
+            self.bit_position += 1;
+        }
+        Ok(result)
+    }
+
+    pub fn read_signed(&mut self, bits: u32) -> Result<i64, String> {
+        let unsigned = self.read(bits)?;
+        if bits == 0 {
+            Ok(0)
+        } else {
+            Ok(((unsigned << (64 - bits)) as i64) >> (64 - bits))
+        }
+    }
+
+    /// counts zeroes until (and consuming) the terminating one
+    pub fn read_unary(&mut self) -> Result<u32, String> {
+        let mut count = 0;
+        while self.read(1)? == 0 {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    pub fn read_rice_signed(&mut self, param: u32) -> Result<i32, String> {
+        let quotient = self.read_unary()? as u64;
+        let folded = (quotient << param) | self.read(param)?;
+        if folded > u32::MAX as u64 {
+            return Err(format!("rice-coded value {} doesn't fit in 32 bits", folded));
+        }
+        Ok(unzigzag(folded as u32))
+    }
+
+    pub fn align_to_byte(&mut self) {
+        self.bit_position = (self.bit_position + 7) & !7;
+    }
+
+    /// the raw bytes between two byte-aligned positions, e.g. for checksumming a header
+    pub fn bytes_between(&self, start_byte: usize, end_byte: usize) -> &'a [u8] {
+        &self.data[start_byte..end_byte]
+    }
+}
+
+pub fn unzigzag(folded: u32) -> i32 {
+    ((folded >> 1) as i32) ^ -((folded & 1) as i32)
+}
+
+/// CRC-8 with polynomial x^8 + x^2 + x^1 + x^0, as used in FLAC frame headers
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// CRC-16 with polynomial x^16 + x^15 + x^2 + x^0, as used in FLAC frame footers
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 };
+        }
+    }
+    crc
+}
@@ -0,0 +1,107 @@
+// Copyright (C) 2021 lifning, licensed under the GNU Affero General Public License version 3.
+
+//! Converted sounds, keyed on a hash of the source file plus everything that goes into
+//! converting it.  Lives outside `target/` so neither switching between debug and release nor
+//! a `cargo clean` means sitting through a full re-encode of the soundtrack.
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use flowergal_proj_config::resources::LoopPoint;
+use flowergal_proj_config::sound_info::{PLAYBUF_SIZE, SAMPLE_RATE};
+
+use crate::music::bit_reduction::BitReductionSettings;
+use crate::music::flac_encoder::EncoderSettings;
+use crate::music::loudness::Levels;
+use crate::music::manifest::{ProjectSettings, SoundEntry};
+
+pub const CACHE_DIR: &str = "../../.cache/sound";
+
+/// bump this whenever a change to the conversion code would change its output
+const CONVERSION_VERSION: u32 = 3;
+
+pub struct ConvertedSound {
+    pub data: Vec<u8>,
+    pub loop_point: Option<LoopPoint>,
+    pub levels: Levels,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheMeta {
+    loop_sample: Option<u32>,
+    loop_byte_offset: Option<u32>,
+    levels: Levels,
+}
+
+pub fn cache_key(entry: &SoundEntry, project: &ProjectSettings, source: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(&CONVERSION_VERSION.to_le_bytes());
+    hasher.update(source);
+    // everything but the id, where the source came from, and the budgets, so renames don't
+    // cost a re-encode
+    let params = format!(
+        "{:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {} {} {:?} {:?}",
+        project.target_loudness_lufs,
+        project.max_true_peak_dbtp,
+        entry.codec,
+        entry.quality,
+        entry.normalize,
+        entry.gain_db,
+        entry.loop_start,
+        entry.loop_end,
+        entry.trims_silence(),
+        SAMPLE_RATE,
+        PLAYBUF_SIZE,
+        EncoderSettings::default(),
+        BitReductionSettings::from(entry.quality),
+    );
+    hasher.update(params.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+fn paths(key: &str) -> (PathBuf, PathBuf) {
+    let dir = Path::new(CACHE_DIR);
+    (dir.join(key).with_extension("bin"), dir.join(key).with_extension("toml"))
+}
+
+pub fn load(key: &str) -> Result<Option<ConvertedSound>, Box<dyn Error>> {
+    let (data_path, meta_path) = paths(key);
+    // the meta file is written last, so if it's there, the data is complete
+    if !meta_path.is_file() || !data_path.is_file() {
+        return Ok(None);
+    }
+    let meta: CacheMeta = toml::from_str(&std::fs::read_to_string(&meta_path)?)?;
+    let loop_point = match (meta.loop_sample, meta.loop_byte_offset) {
+        (Some(sample), Some(byte_offset)) => Some(LoopPoint {
+            sample,
+            byte_offset,
+        }),
+        _ => None,
+    };
+    Ok(Some(ConvertedSound {
+        data: std::fs::read(&data_path)?,
+        loop_point,
+        levels: meta.levels,
+    }))
+}
+
+pub fn store(key: &str, sound: &ConvertedSound) -> Result<(), Box<dyn Error>> {
+    std::fs::create_dir_all(CACHE_DIR)?;
+    let (data_path, meta_path) = paths(key);
+    let meta = CacheMeta {
+        loop_sample: sound.loop_point.map(|lp| lp.sample),
+        loop_byte_offset: sound.loop_point.map(|lp| lp.byte_offset),
+        levels: sound.levels,
+    };
+    // write-then-rename, in case a debug and a release build are converting the same thing
+    let temp_path = data_path.with_extension(format!("bin.{}.tmp", std::process::id()));
+    std::fs::write(&temp_path, &sound.data)?;
+    std::fs::rename(&temp_path, &data_path)?;
+    let temp_path = meta_path.with_extension(format!("toml.{}.tmp", std::process::id()));
+    std::fs::write(&temp_path, toml::to_string(&meta)?)?;
+    std::fs::rename(&temp_path, &meta_path)?;
+    Ok(())
+}
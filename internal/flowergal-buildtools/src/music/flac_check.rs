@@ -0,0 +1,424 @@
+// Copyright (C) 2021 lifning, licensed under the GNU Affero General Public License version 3.
+
+//! Decodes a FLAC stream the way `SimpleFlac` would, failing loudly (with the frame and field
+//! at fault) on anything the runtime decoder doesn't support or would silently get wrong,
+//! instead of us finding out from a `fatal!` on the console.
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use flowergal_proj_config::sound_info::{PLAYBUF_SIZE, SAMPLE_RATE};
+
+use crate::music::bitstream::{crc16, crc8, zigzag, BitReader};
+use crate::music::flac_encoder::{DecodeCostModel, MAX_FIXED_ORDER, MAX_LPC_ORDER};
+
+const FIXED_PREDICTION_COEFFICIENTS: [&[i64]; MAX_FIXED_ORDER + 1] = [
+    &[],
+    &[1],
+    &[2, -1],
+    &[3, -3, 1],
+    &[4, -6, 4, -1],
+];
+
+#[derive(Debug)]
+pub struct ConformanceError {
+    /// `None` for problems in the stream header
+    pub frame: Option<usize>,
+    pub byte_offset: usize,
+    pub field: &'static str,
+    pub problem: String,
+}
+
+impl Display for ConformanceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.frame {
+            Some(frame) => write!(f, "frame {} (byte {:#x})", frame, self.byte_offset)?,
+            None => write!(f, "stream header (byte {:#x})", self.byte_offset)?,
+        }
+        write!(f, ", {}: {}", self.field, self.problem)
+    }
+}
+
+impl Error for ConformanceError {}
+
+/// everything we learned from a stream that passed
+pub struct CheckedFlac {
+    pub sample_rate: u32,
+    pub sample_depth: u32,
+    pub samples: Vec<i32>,
+    /// where each frame's sync code starts, i.e. the places it's safe to loop back to
+    pub frame_offsets: Vec<usize>,
+    /// what the encoder's cost model says each frame takes to decode, going by what's
+    /// actually in the stream (so it holds for cached encodes too)
+    pub frame_cycles: Vec<u32>,
+}
+
+struct Checker<'a> {
+    reader: BitReader<'a>,
+    frame: Option<usize>,
+    cost: DecodeCostModel,
+    cycles: u32,
+}
+
+impl<'a> Checker<'a> {
+    fn fail(&self, field: &'static str, problem: impl Into<String>) -> ConformanceError {
+        ConformanceError {
+            frame: self.frame,
+            byte_offset: self.reader.byte_position(),
+            field,
+            problem: problem.into(),
+        }
+    }
+
+    fn read(&mut self, bits: u32, field: &'static str) -> Result<u64, ConformanceError> {
+        self.reader.read(bits).map_err(|e| self.fail(field, e))
+    }
+
+    fn read_signed(&mut self, bits: u32, field: &'static str) -> Result<i64, ConformanceError> {
+        self.reader.read_signed(bits).map_err(|e| self.fail(field, e))
+    }
+
+    fn read_unary(&mut self, field: &'static str) -> Result<u32, ConformanceError> {
+        self.reader.read_unary().map_err(|e| self.fail(field, e))
+    }
+}
+
+pub fn check_flac(bytes: &[u8]) -> Result<CheckedFlac, ConformanceError> {
+    let mut c = Checker {
+        reader: BitReader::new(bytes),
+        frame: None,
+        cost: DecodeCostModel::default(),
+        cycles: 0,
+    };
+
+    if c.read(32, "magic")? != 0x664C6143 {
+        return Err(c.fail("magic", "not a FLAC stream"));
+    }
+
+    let mut stream_info = None;
+    let mut last = false;
+    while !last {
+        last = c.read(1, "metadata block header")? != 0;
+        let block_type = c.read(7, "metadata block type")?;
+        let length = c.read(24, "metadata block length")? as usize;
+        if block_type == 0 {
+            let min_blocksize = c.read(16, "STREAMINFO min blocksize")? as usize;
+            let max_blocksize = c.read(16, "STREAMINFO max blocksize")? as usize;
+            c.read(24 + 24, "STREAMINFO frame sizes")?;
+            let sample_rate = c.read(20, "STREAMINFO sample rate")? as u32;
+            let channels = c.read(3, "STREAMINFO channels")? + 1;
+            let sample_depth = c.read(5, "STREAMINFO sample depth")? as u32 + 1;
+            let total_samples = c.read(36, "STREAMINFO total samples")? as usize;
+            c.read(64, "STREAMINFO MD5")?;
+            c.read(64, "STREAMINFO MD5")?;
+
+            if channels != 1 {
+                return Err(c.fail("STREAMINFO channels", format!("{} channels, SimpleFlac only does mono", channels)));
+            }
+            if sample_rate != SAMPLE_RATE as u32 {
+                return Err(c.fail(
+                    "STREAMINFO sample rate",
+                    format!("{}Hz, but the mixer always plays at {}Hz", sample_rate, SAMPLE_RATE),
+                ));
+            }
+            if sample_depth & 7 != 0 {
+                return Err(c.fail("STREAMINFO sample depth", format!("{} bits isn't a multiple of 8", sample_depth)));
+            }
+            if min_blocksize != PLAYBUF_SIZE || max_blocksize != PLAYBUF_SIZE {
+                return Err(c.fail(
+                    "STREAMINFO blocksize",
+                    format!("{}..={}, must always be PLAYBUF_SIZE ({})", min_blocksize, max_blocksize, PLAYBUF_SIZE),
+                ));
+            }
+            if total_samples % PLAYBUF_SIZE != 0 {
+                return Err(c.fail(
+                    "STREAMINFO total samples",
+                    format!("{} isn't a whole number of frames", total_samples),
+                ));
+            }
+            stream_info = Some((sample_rate, sample_depth, total_samples));
+        } else {
+            for _ in 0..length {
+                c.read(8, "metadata block")?;
+            }
+        }
+    }
+    let (sample_rate, sample_depth, total_samples) = match stream_info {
+        Some(x) => x,
+        None => return Err(c.fail("STREAMINFO", "metadata block absent")),
+    };
+
+    let mut samples = Vec::with_capacity(total_samples);
+    let mut frame_offsets = Vec::with_capacity(total_samples / PLAYBUF_SIZE);
+    let mut frame_cycles = Vec::with_capacity(total_samples / PLAYBUF_SIZE);
+    while !c.reader.is_empty() {
+        c.frame = Some(frame_offsets.len());
+        frame_offsets.push(c.reader.byte_position());
+        c.cycles = c.cost.frame_overhead;
+        check_frame(&mut c, sample_depth, &mut samples)?;
+        frame_cycles.push(c.cycles);
+    }
+
+    c.frame = None;
+    if samples.len() != total_samples {
+        return Err(c.fail(
+            "STREAMINFO total samples",
+            format!("says {}, but the frames hold {}", total_samples, samples.len()),
+        ));
+    }
+
+    Ok(CheckedFlac {
+        sample_rate,
+        sample_depth,
+        samples,
+        frame_offsets,
+        frame_cycles,
+    })
+}
+
+fn check_frame(c: &mut Checker, sample_depth: u32, samples: &mut Vec<i32>) -> Result<(), ConformanceError> {
+    let frame_start = c.reader.byte_position();
+
+    if c.read(14, "sync code")? != 0x3FFE {
+        return Err(c.fail("sync code", "missing"));
+    }
+    if c.read(1, "reserved header bit")? != 0 {
+        return Err(c.fail("reserved header bit", "set"));
+    }
+    c.read(1, "blocking strategy")?;
+    let blocksize_code = c.read(4, "blocksize code")?;
+    let sample_rate_code = c.read(4, "sample rate code")?;
+    let channel_assignment = c.read(4, "channel assignment")?;
+    let sample_size_code = c.read(3, "sample size code")?;
+    if c.read(1, "reserved header bit")? != 0 {
+        return Err(c.fail("reserved header bit", "set"));
+    }
+
+    if channel_assignment != 0 {
+        return Err(c.fail("channel assignment", format!("{}, SimpleFlac only does mono", channel_assignment)));
+    }
+    // SimpleFlac always goes by STREAMINFO's depth
+    let header_depth = match sample_size_code {
+        0 => Some(sample_depth),
+        1 => Some(8),
+        2 => Some(12),
+        4 => Some(16),
+        5 => Some(20),
+        6 => Some(24),
+        7 => Some(32),
+        _ => None,
+    };
+    if header_depth != Some(sample_depth) {
+        return Err(c.fail(
+            "sample size code",
+            format!("{} disagrees with STREAMINFO's {} bits", sample_size_code, sample_depth),
+        ));
+    }
+
+    // frame number, in FLAC's UTF-8-like encoding
+    let first = c.read(8, "frame number")?;
+    let continuation_bytes = match (first as u8).leading_ones() {
+        0 => 0,
+        n @ 2..=7 => n - 1,
+        _ => return Err(c.fail("frame number", format!("malformed leading byte {:#x}", first))),
+    };
+    for _ in 0..continuation_bytes {
+        if c.read(8, "frame number")? & 0xC0 != 0x80 {
+            return Err(c.fail("frame number", "malformed continuation byte"));
+        }
+    }
+
+    let blocksize = match blocksize_code {
+        1 => 192,
+        2..=5 => 576 << (blocksize_code - 2),
+        6 => c.read(8, "blocksize")? as usize + 1,
+        7 => c.read(16, "blocksize")? as usize + 1,
+        8..=15 => 256 << (blocksize_code - 8),
+        _ => return Err(c.fail("blocksize code", "reserved value 0")),
+    };
+    if blocksize != PLAYBUF_SIZE {
+        return Err(c.fail("blocksize", format!("{}, must be PLAYBUF_SIZE ({})", blocksize, PLAYBUF_SIZE)));
+    }
+
+    match sample_rate_code {
+        12 => {
+            c.read(8, "sample rate")?;
+        }
+        13 | 14 => {
+            c.read(16, "sample rate")?;
+        }
+        15 => return Err(c.fail("sample rate code", "invalid value 15")),
+        _ => {}
+    }
+
+    let header_end = c.reader.byte_position();
+    let expected_crc8 = crc8(c.reader.bytes_between(frame_start, header_end));
+    if c.read(8, "header CRC-8")? as u8 != expected_crc8 {
+        return Err(c.fail("header CRC-8", "mismatch"));
+    }
+
+    check_subframe(c, sample_depth, blocksize, samples)?;
+
+    c.reader.align_to_byte();
+    let footer_start = c.reader.byte_position();
+    let expected_crc16 = crc16(c.reader.bytes_between(frame_start, footer_start));
+    if c.read(16, "footer CRC-16")? as u16 != expected_crc16 {
+        return Err(c.fail("footer CRC-16", "mismatch"));
+    }
+
+    Ok(())
+}
+
+fn check_subframe(
+    c: &mut Checker,
+    sample_depth: u32,
+    blocksize: usize,
+    samples: &mut Vec<i32>,
+) -> Result<(), ConformanceError> {
+    if c.read(1, "subframe padding bit")? != 0 {
+        return Err(c.fail("subframe padding bit", "set"));
+    }
+    let subframe_type = c.read(6, "subframe type")? as usize;
+    let wasted_bits = if c.read(1, "wasted bits flag")? != 0 {
+        c.read_unary("wasted bits")? + 1
+    } else {
+        0
+    };
+    if wasted_bits >= sample_depth {
+        return Err(c.fail("wasted bits", format!("{} leaves nothing of a {}-bit sample", wasted_bits, sample_depth)));
+    }
+    let depth = sample_depth - wasted_bits;
+
+    let mut block = Vec::with_capacity(blocksize);
+    match subframe_type {
+        0 => {
+            let value = c.read_signed(depth, "constant value")?;
+            block.resize(blocksize, value);
+            c.cycles += blocksize as u32 * c.cost.per_constant_sample;
+        }
+        1 => {
+            for _ in 0..blocksize {
+                block.push(c.read_signed(depth, "verbatim sample")?);
+            }
+            c.cycles += blocksize as u32 * c.cost.per_verbatim_sample;
+        }
+        8..=15 => {
+            let order = subframe_type - 8;
+            if order > MAX_FIXED_ORDER {
+                return Err(c.fail("fixed predictor order", format!("{} is reserved", order)));
+            }
+            for _ in 0..order {
+                block.push(c.read_signed(depth, "warmup sample")?);
+            }
+            c.cycles += order as u32 * c.cost.per_verbatim_sample;
+            check_residuals(c, order, blocksize, &mut block)?;
+            restore_prediction(c, &mut block, FIXED_PREDICTION_COEFFICIENTS[order], 0)?;
+        }
+        32..=63 => {
+            let order = subframe_type - 31;
+            if order > MAX_LPC_ORDER {
+                return Err(c.fail(
+                    "LPC order",
+                    format!("{} exceeds SimpleFlac's limit of {}", order, MAX_LPC_ORDER),
+                ));
+            }
+            for _ in 0..order {
+                block.push(c.read_signed(depth, "warmup sample")?);
+            }
+            c.cycles += order as u32 * c.cost.per_verbatim_sample;
+            let precision = c.read(4, "LPC precision")? as u32 + 1;
+            if precision == 16 {
+                return Err(c.fail("LPC precision", "invalid value"));
+            }
+            let shift = c.read_signed(5, "LPC shift")? as i32;
+            if shift < 0 {
+                return Err(c.fail("LPC shift", format!("{} is negative, which SimpleFlac's asr can't do", shift)));
+            }
+            let mut coefs = Vec::with_capacity(order);
+            for _ in 0..order {
+                coefs.push(c.read_signed(precision, "LPC coefficient")?);
+            }
+            check_residuals(c, order, blocksize, &mut block)?;
+            restore_prediction(c, &mut block, &coefs, shift)?;
+        }
+        _ => return Err(c.fail("subframe type", format!("{} is reserved", subframe_type))),
+    }
+
+    let min = -(1i64 << (depth - 1));
+    let max = (1i64 << (depth - 1)) - 1;
+    if let Some((i, x)) = block.iter().enumerate().find(|(_, x)| **x < min || **x > max) {
+        return Err(c.fail("decoded sample", format!("sample {} ({}) doesn't fit in {} bits", i, x, depth)));
+    }
+    samples.extend(block.iter().map(|x| (*x << wasted_bits) as i32));
+
+    Ok(())
+}
+
+fn check_residuals(
+    c: &mut Checker,
+    order: usize,
+    blocksize: usize,
+    block: &mut Vec<i64>,
+) -> Result<(), ConformanceError> {
+    let (param_bits, escape) = match c.read(2, "residual coding method")? {
+        0 => (4, 0xF),
+        1 => (5, 0x1F),
+        method => return Err(c.fail("residual coding method", format!("{} is reserved", method))),
+    };
+    let partition_order = c.read(4, "partition order")?;
+    let partitions = 1usize << partition_order;
+    if blocksize % partitions != 0 || blocksize / partitions < order {
+        return Err(c.fail(
+            "partition order",
+            format!("{} doesn't divide a {}-sample block with order {} prediction", partition_order, blocksize, order),
+        ));
+    }
+
+    for partition in 0..partitions {
+        let mut count = blocksize / partitions;
+        if partition == 0 {
+            count -= order;
+        }
+        let param = c.read(param_bits, "rice parameter")? as u32;
+        c.cycles += c.cost.partition_overhead;
+        if param == escape {
+            let width = c.read(5, "escaped partition width")? as u32;
+            if width == 0 {
+                // read_signed_int(0) shifts by -1
+                return Err(c.fail("escaped partition width", "0, which SimpleFlac can't read"));
+            }
+            for _ in 0..count {
+                block.push(c.read_signed(width, "escaped residual")?);
+            }
+            c.cycles += count as u32 * c.cost.per_escaped_sample;
+        } else {
+            for _ in 0..count {
+                let value = c.reader.read_rice_signed(param).map_err(|e| c.fail("rice residual", e))?;
+                // SimpleFlac folds in an i32, so the top bit of the zigzagged value must be clear
+                if !(-(1 << 30)..(1 << 30)).contains(&value) {
+                    return Err(c.fail("rice residual", format!("{} too large for SimpleFlac's i32 unfolding", value)));
+                }
+                block.push(value as i64);
+                let quotient = zigzag(value as i32) >> param;
+                c.cycles += c.cost.per_rice_sample + quotient.saturating_mul(c.cost.per_rice_quotient);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// like `SimpleFlac::restore_linear_prediction`, but checking that its 32-bit `mla` chains
+/// wouldn't have overflowed.
+fn restore_prediction(c: &mut Checker, block: &mut [i64], coefs: &[i64], shift: i32) -> Result<(), ConformanceError> {
+    let order = coefs.len();
+    c.cycles += (block.len() - order) as u32 * c.cost.per_restored_sample[order];
+    for i in order..block.len() {
+        let sum: i64 = coefs.iter().enumerate().map(|(j, coef)| coef * block[i - 1 - j]).sum();
+        if sum < i32::MIN as i64 || sum > i32::MAX as i64 {
+            return Err(c.fail("prediction", format!("sample {} overflows a 32-bit accumulator ({})", i, sum)));
+        }
+        block[i] += sum >> shift;
+    }
+    Ok(())
+}
@@ -0,0 +1,773 @@
+// Copyright (C) 2021 lifning, licensed under the GNU Affero General Public License version 3.
+
+//! A FLAC encoder that only ever emits the subset of the format `SimpleFlac` can play:
+//! mono, 16-bit, every frame exactly `PLAYBUF_SIZE` samples, fixed predictors up to order 4,
+//! LPC up to order 4 (with coefficients small enough that the decoder's 32-bit `mla` chains
+//! can't overflow), and no zero-width escaped partitions.
+//!
+//! Candidates are scored on encoded size *and* on roughly how many cycles the GBA will spend
+//! decoding them, since a frame that decodes too slowly starves the mixer no matter how small.
+
+use std::error::Error;
+
+use rayon::prelude::*;
+
+use flowergal_proj_config::sound_info::{CYCLES_PER_FRAME, PLAYBUF_SIZE, SAMPLE_RATE};
+
+use crate::music::bitstream::{crc16, crc8, zigzag, BitWriter};
+
+pub const SAMPLE_DEPTH: u32 = 16;
+/// `SimpleFlac` collects coefficients into a `heapless::Vec<_, U4>`
+pub const MAX_LPC_ORDER: usize = 4;
+pub const MAX_FIXED_ORDER: usize = 4;
+/// with 16-bit samples, 4 * 2^15 * 2^12 still fits comfortably in an i32 accumulator
+pub const MAX_LPC_PRECISION: u32 = 13;
+/// shift is a 5-bit signed field, and the decoder's `asr` can't do negative shifts anyway
+pub const MAX_LPC_SHIFT: i32 = 15;
+pub const MAX_PARTITION_ORDER: u32 = 8;
+const LPC_PRECISIONS: &[u32] = &[9, 11, MAX_LPC_PRECISION];
+
+const RICE_PARAM_BITS: u32 = 4;
+const RICE_ESCAPE: u32 = 0xF;
+const RICE2_PARAM_BITS: u32 = 5;
+const RICE2_ESCAPE: u32 = 0x1F;
+const MAX_RICE2_PARAM: u32 = RICE2_ESCAPE - 1;
+/// escaped partitions store their sample width in a 5-bit field
+const MAX_ESCAPE_BITS: u32 = 31;
+
+const FIXED_PREDICTION_COEFFICIENTS: [&[i64]; MAX_FIXED_ORDER + 1] = [
+    &[],
+    &[1],
+    &[2, -1],
+    &[3, -3, 1],
+    &[4, -6, 4, -1],
+];
+
+/// Ballpark cycle costs of the pieces of `SimpleFlac::decode_frame`, eyeballed from
+/// `bench_flac` logs on hardware with our WAITCNT settings.  Not cycle-exact, but close enough
+/// to rank candidates against each other and to flag frames that risk starving the mixer.
+#[derive(Clone, Debug)]
+pub struct DecodeCostModel {
+    /// header parsing, footer, and the unconditional wasted-bits shift loop
+    pub frame_overhead: u32,
+    pub partition_overhead: u32,
+    /// also used for warmup samples, which are read the same way
+    pub per_verbatim_sample: u32,
+    pub per_constant_sample: u32,
+    pub per_rice_sample: u32,
+    /// each bit of unary quotient is another trip around the loop in `count_golomb_rice_quotient`
+    pub per_rice_quotient: u32,
+    pub per_escaped_sample: u32,
+    /// indexed by predictor order, for the hand-written loops in `restore_linear_prediction`
+    pub per_restored_sample: [u32; MAX_LPC_ORDER + 1],
+}
+
+impl Default for DecodeCostModel {
+    fn default() -> Self {
+        DecodeCostModel {
+            frame_overhead: 600 + 3 * PLAYBUF_SIZE as u32,
+            partition_overhead: 60,
+            per_verbatim_sample: 40,
+            per_constant_sample: 1,
+            per_rice_sample: 48,
+            per_rice_quotient: 9,
+            per_escaped_sample: 40,
+            per_restored_sample: [0, 6, 8, 10, 12],
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct EncoderSettings {
+    /// how many bits of output we're willing to spend to save one cycle of decoding
+    pub cycle_weight: f64,
+    /// frames predicted to cost more than this fall back to whichever candidate decodes fastest
+    pub max_frame_cycles: u32,
+    pub cost_model: DecodeCostModel,
+}
+
+impl Default for EncoderSettings {
+    fn default() -> Self {
+        EncoderSettings {
+            cycle_weight: 0.01,
+            max_frame_cycles: CYCLES_PER_FRAME / 3,
+            cost_model: DecodeCostModel::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ResidualParam {
+    Rice(u32),
+    Escape(u32),
+}
+
+#[derive(Clone, Debug)]
+struct ResidualPlan {
+    partition_order: u32,
+    /// method 1 (5-bit Rice parameters) is only used if some partition needs a parameter > 14
+    wide_params: bool,
+    params: Vec<ResidualParam>,
+    bits: usize,
+    cycles: u32,
+}
+
+#[derive(Clone, Debug)]
+pub enum SubframeKind {
+    Constant,
+    Verbatim,
+    Fixed(usize),
+    Lpc {
+        precision: u32,
+        shift: i32,
+        coefs: Vec<i32>,
+    },
+}
+
+impl SubframeKind {
+    pub fn order(&self) -> usize {
+        match self {
+            SubframeKind::Constant | SubframeKind::Verbatim => 0,
+            SubframeKind::Fixed(order) => *order,
+            SubframeKind::Lpc { coefs, .. } => coefs.len(),
+        }
+    }
+
+    fn type_code(&self) -> u32 {
+        match self {
+            SubframeKind::Constant => 0,
+            SubframeKind::Verbatim => 1,
+            SubframeKind::Fixed(order) => 8 + *order as u32,
+            SubframeKind::Lpc { coefs, .. } => 31 + coefs.len() as u32,
+        }
+    }
+}
+
+struct Subframe {
+    kind: SubframeKind,
+    wasted_bits: u32,
+    /// already shifted right by `wasted_bits`
+    samples: Vec<i32>,
+    residual: Vec<i32>,
+    plan: Option<ResidualPlan>,
+    bits: usize,
+    cycles: u32,
+}
+
+/// What the encoder decided for each frame, for build reports.
+#[derive(Clone, Debug)]
+pub struct FrameStats {
+    pub kind: SubframeKind,
+    pub wasted_bits: u32,
+    pub partition_order: u32,
+    pub bytes: usize,
+    pub predicted_cycles: u32,
+}
+
+pub struct EncodedFlac {
+    pub bytes: Vec<u8>,
+    pub frames: Vec<FrameStats>,
+    /// including the silence padding out the final frame
+    pub sample_count: usize,
+}
+
+pub fn encode_flac(
+    samples: &[i16],
+    settings: &EncoderSettings,
+) -> Result<EncodedFlac, Box<dyn Error>> {
+    if samples.is_empty() {
+        return Err("Refusing to encode an empty FLAC stream".into());
+    }
+
+    // pad out the last block so every frame is exactly PLAYBUF_SIZE, no special cases at runtime
+    let mut padded: Vec<i32> = samples.iter().map(|x| *x as i32).collect();
+    while padded.len() % PLAYBUF_SIZE != 0 {
+        padded.push(0);
+    }
+
+    let encoded_frames: Vec<(Vec<u8>, FrameStats)> = padded
+        .par_chunks(PLAYBUF_SIZE)
+        .enumerate()
+        .map(|(frame_number, block)| encode_frame(frame_number as u32, block, settings))
+        .collect();
+
+    let min_frame_size = encoded_frames.iter().map(|(b, _)| b.len()).min().unwrap_or(0);
+    let max_frame_size = encoded_frames.iter().map(|(b, _)| b.len()).max().unwrap_or(0);
+
+    let mut out = BitWriter::new();
+    out.write_bytes(b"fLaC");
+    // metadata block header: last block, type 0 (STREAMINFO), length
+    out.write(1, 1);
+    out.write(0, 7);
+    out.write(34, 24);
+    out.write(PLAYBUF_SIZE as u64, 16);
+    out.write(PLAYBUF_SIZE as u64, 16);
+    out.write(min_frame_size as u64, 24);
+    out.write(max_frame_size as u64, 24);
+    out.write(SAMPLE_RATE as u64, 20);
+    out.write(0, 3); // channels - 1
+    out.write((SAMPLE_DEPTH - 1) as u64, 5);
+    out.write((padded.len() as u64) >> 32, 4);
+    out.write(padded.len() as u64, 32);
+    // MD5 of the unencoded audio. all zeroes means "not computed", which is allowed,
+    // and SimpleFlac doesn't read it anyway.
+    for _ in 0..4 {
+        out.write(0, 32);
+    }
+
+    let mut bytes = out.into_bytes();
+    let mut frames = Vec::with_capacity(encoded_frames.len());
+    for (frame_bytes, stats) in encoded_frames {
+        bytes.extend_from_slice(&frame_bytes);
+        frames.push(stats);
+    }
+
+    Ok(EncodedFlac {
+        bytes,
+        frames,
+        sample_count: padded.len(),
+    })
+}
+
+fn encode_frame(frame_number: u32, block: &[i32], settings: &EncoderSettings) -> (Vec<u8>, FrameStats) {
+    let subframe = choose_subframe(block, settings);
+
+    let mut header = BitWriter::new();
+    header.write(0x3FFE, 14); // sync code
+    header.write(0, 1); // reserved
+    header.write(0, 1); // fixed blocksize stream
+    header.write(7, 4); // blocksize - 1 stored as 16 bits at end of header
+    header.write(0, 4); // sample rate from STREAMINFO
+    header.write(0, 4); // mono
+    header.write(0b100, 3); // 16 bits per sample
+    header.write(0, 1); // reserved
+    write_utf8_number(&mut header, frame_number);
+    header.write((block.len() - 1) as u64, 16);
+    let mut header_bytes = header.into_bytes();
+    let header_crc = crc8(&header_bytes);
+    header_bytes.push(header_crc);
+
+    let mut frame = BitWriter::new();
+    frame.write_bytes(&header_bytes);
+    write_subframe(&mut frame, &subframe);
+    frame.align_to_byte();
+    let frame_crc = crc16(frame.bytes());
+    frame.write(frame_crc as u64, 16);
+    let bytes = frame.into_bytes();
+
+    let stats = FrameStats {
+        partition_order: subframe.plan.as_ref().map(|p| p.partition_order).unwrap_or(0),
+        kind: subframe.kind,
+        wasted_bits: subframe.wasted_bits,
+        bytes: bytes.len(),
+        predicted_cycles: subframe.cycles,
+    };
+    (bytes, stats)
+}
+
+/// the "UTF-8" coded frame number in each frame header
+fn write_utf8_number(w: &mut BitWriter, value: u32) {
+    if value < 0x80 {
+        w.write(value as u64, 8);
+        return;
+    }
+    let num_bytes = match value {
+        0..=0x7FF => 2,
+        0x800..=0xFFFF => 3,
+        0x1_0000..=0x1F_FFFF => 4,
+        0x20_0000..=0x3FF_FFFF => 5,
+        _ => 6,
+    };
+    let prefix = (0xFF00u32 >> num_bytes) & 0xFF;
+    w.write((prefix | (value >> (6 * (num_bytes - 1)))) as u64, 8);
+    for i in (0..num_bytes - 1).rev() {
+        w.write((0x80 | ((value >> (6 * i)) & 0x3F)) as u64, 8);
+    }
+}
+
+fn write_subframe(w: &mut BitWriter, subframe: &Subframe) {
+    let depth = SAMPLE_DEPTH - subframe.wasted_bits;
+    w.write(0, 1);
+    w.write(subframe.kind.type_code() as u64, 6);
+    if subframe.wasted_bits > 0 {
+        w.write(1, 1);
+        w.write_unary(subframe.wasted_bits - 1);
+    } else {
+        w.write(0, 1);
+    }
+
+    let order = subframe.kind.order();
+    match &subframe.kind {
+        SubframeKind::Constant => w.write_signed(subframe.samples[0] as i64, depth),
+        SubframeKind::Verbatim => {
+            for x in &subframe.samples {
+                w.write_signed(*x as i64, depth);
+            }
+        }
+        SubframeKind::Fixed(_) => {
+            for x in &subframe.samples[..order] {
+                w.write_signed(*x as i64, depth);
+            }
+        }
+        SubframeKind::Lpc {
+            precision,
+            shift,
+            coefs,
+        } => {
+            for x in &subframe.samples[..order] {
+                w.write_signed(*x as i64, depth);
+            }
+            w.write((precision - 1) as u64, 4);
+            w.write_signed(*shift as i64, 5);
+            for c in coefs {
+                w.write_signed(*c as i64, *precision);
+            }
+        }
+    }
+
+    if let Some(plan) = &subframe.plan {
+        let (param_bits, escape) = if plan.wide_params {
+            (RICE2_PARAM_BITS, RICE2_ESCAPE)
+        } else {
+            (RICE_PARAM_BITS, RICE_ESCAPE)
+        };
+        w.write(plan.wide_params as u64, 2);
+        w.write(plan.partition_order as u64, 4);
+        let len = subframe.residual.len() >> plan.partition_order;
+        for (i, param) in plan.params.iter().enumerate() {
+            let start = if i == 0 { order } else { i * len };
+            let values = &subframe.residual[start..(i + 1) * len];
+            match param {
+                ResidualParam::Rice(k) => {
+                    w.write(*k as u64, param_bits);
+                    for x in values {
+                        w.write_rice_signed(*x, *k);
+                    }
+                }
+                ResidualParam::Escape(bits) => {
+                    w.write(escape as u64, param_bits);
+                    w.write(*bits as u64, 5);
+                    for x in values {
+                        w.write_signed(*x as i64, *bits);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn score(bits: usize, cycles: u32, settings: &EncoderSettings) -> f64 {
+    bits as f64 + settings.cycle_weight * cycles as f64
+}
+
+fn choose_subframe(block: &[i32], settings: &EncoderSettings) -> Subframe {
+    let cost = &settings.cost_model;
+    let n = block.len();
+
+    if block.iter().all(|x| *x == block[0]) {
+        return Subframe {
+            kind: SubframeKind::Constant,
+            wasted_bits: 0,
+            samples: block.to_vec(),
+            residual: Vec::new(),
+            plan: None,
+            bits: 8 + SAMPLE_DEPTH as usize,
+            cycles: cost.frame_overhead + n as u32 * cost.per_constant_sample,
+        };
+    }
+
+    // lossy preprocessing leaves a lot of zeroes at the bottom of each block
+    let all_bits = block.iter().fold(0, |acc, x| acc | *x);
+    let wasted_bits = all_bits.trailing_zeros().min(SAMPLE_DEPTH - 1);
+    let samples: Vec<i32> = block.iter().map(|x| *x >> wasted_bits).collect();
+    let depth = (SAMPLE_DEPTH - wasted_bits) as usize;
+    let header_bits = 8 + wasted_bits as usize;
+
+    let mut candidates = Vec::new();
+
+    candidates.push(Subframe {
+        kind: SubframeKind::Verbatim,
+        wasted_bits,
+        samples: samples.clone(),
+        residual: Vec::new(),
+        plan: None,
+        bits: header_bits + n * depth,
+        cycles: cost.frame_overhead + n as u32 * cost.per_verbatim_sample,
+    });
+
+    let mut predicted = Vec::new();
+    for order in 0..=MAX_FIXED_ORDER {
+        if let Some(residual) = fixed_residual(&samples, order) {
+            predicted.push((SubframeKind::Fixed(order), residual, 0));
+        }
+    }
+    for (coefs, precision, shift) in lpc_candidates(&samples) {
+        if let Some(residual) = lpc_residual(&samples, &coefs, shift) {
+            let order = coefs.len();
+            let extra_bits = 4 + 5 + order * precision as usize;
+            predicted.push((
+                SubframeKind::Lpc {
+                    precision,
+                    shift,
+                    coefs,
+                },
+                residual,
+                extra_bits,
+            ));
+        }
+    }
+
+    for (kind, residual, extra_bits) in predicted {
+        let order = kind.order();
+        if let Some(plan) = plan_residual(&residual, order, settings) {
+            let bits = header_bits + order * depth + extra_bits + plan.bits;
+            let cycles = cost.frame_overhead
+                + order as u32 * cost.per_verbatim_sample
+                + plan.cycles
+                + (n - order) as u32 * cost.per_restored_sample[order];
+            candidates.push(Subframe {
+                kind,
+                wasted_bits,
+                samples: samples.clone(),
+                residual,
+                plan: Some(plan),
+                bits,
+                cycles,
+            });
+        }
+    }
+
+    let within_budget = candidates
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| c.cycles <= settings.max_frame_cycles)
+        .min_by(|(_, a), (_, b)| {
+            score(a.bits, a.cycles, settings)
+                .partial_cmp(&score(b.bits, b.cycles, settings))
+                .unwrap()
+        })
+        .map(|(i, _)| i);
+    let chosen = within_budget.unwrap_or_else(|| {
+        candidates
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, c)| c.cycles)
+            .map(|(i, _)| i)
+            .unwrap()
+    });
+    candidates.swap_remove(chosen)
+}
+
+fn fixed_residual(samples: &[i32], order: usize) -> Option<Vec<i32>> {
+    let coefs = FIXED_PREDICTION_COEFFICIENTS[order];
+    let mut residual = vec![0; samples.len()];
+    for i in order..samples.len() {
+        let mut prediction = 0i64;
+        for (j, c) in coefs.iter().enumerate() {
+            prediction += c * samples[i - 1 - j] as i64;
+        }
+        residual[i] = fits_i32(samples[i] as i64 - prediction)?;
+    }
+    Some(residual)
+}
+
+fn lpc_residual(samples: &[i32], coefs: &[i32], shift: i32) -> Option<Vec<i32>> {
+    let mut residual = vec![0; samples.len()];
+    for i in coefs.len()..samples.len() {
+        let mut sum = 0i64;
+        for (j, c) in coefs.iter().enumerate() {
+            sum += *c as i64 * samples[i - 1 - j] as i64;
+        }
+        // the decoder accumulates in 32 bits, so don't hand it anything that'd wrap
+        fits_i32(sum)?;
+        residual[i] = fits_i32(samples[i] as i64 - (sum >> shift))?;
+    }
+    Some(residual)
+}
+
+fn fits_i32(x: i64) -> Option<i32> {
+    // leave a bit of headroom so zigzag folding can't overflow either
+    if x.abs() < (1 << 30) {
+        Some(x as i32)
+    } else {
+        None
+    }
+}
+
+/// quantized LPC coefficients for each order up to MAX_LPC_ORDER, at each of a few precisions.
+fn lpc_candidates(samples: &[i32]) -> Vec<(Vec<i32>, u32, i32)> {
+    let n = samples.len();
+    // hann window
+    let windowed: Vec<f64> = samples
+        .iter()
+        .enumerate()
+        .map(|(i, x)| {
+            let w = 0.5 - 0.5 * (2.0 * std::f64::consts::PI * i as f64 / (n - 1) as f64).cos();
+            *x as f64 * w
+        })
+        .collect();
+
+    let mut autoc = [0.0f64; MAX_LPC_ORDER + 1];
+    for (lag, a) in autoc.iter_mut().enumerate() {
+        *a = windowed[lag..]
+            .iter()
+            .zip(windowed.iter())
+            .map(|(x, y)| x * y)
+            .sum();
+    }
+    if autoc[0] <= 0.0 {
+        return Vec::new();
+    }
+
+    let mut results = Vec::new();
+    for lp in levinson_durbin(&autoc) {
+        for precision in LPC_PRECISIONS {
+            if let Some((coefs, shift)) = quantize_coefficients(&lp, *precision) {
+                results.push((coefs, *precision, shift));
+            }
+        }
+    }
+    results
+}
+
+/// linear prediction coefficients for each order 1..=MAX_LPC_ORDER (fewer if the signal is
+/// perfectly predictable sooner), in the same sign convention FLAC uses.
+fn levinson_durbin(autoc: &[f64; MAX_LPC_ORDER + 1]) -> Vec<Vec<f64>> {
+    let mut lpc = [0.0f64; MAX_LPC_ORDER];
+    let mut err = autoc[0];
+    let mut results = Vec::new();
+    for i in 0..MAX_LPC_ORDER {
+        let mut r = -autoc[i + 1];
+        for j in 0..i {
+            r -= lpc[j] * autoc[i - j];
+        }
+        r /= err;
+        lpc[i] = r;
+        let half = i / 2;
+        for j in 0..half {
+            let tmp = lpc[j];
+            lpc[j] += r * lpc[i - 1 - j];
+            lpc[i - 1 - j] += r * tmp;
+        }
+        if i & 1 != 0 {
+            lpc[half] += lpc[half] * r;
+        }
+        err *= 1.0 - r * r;
+        results.push(lpc[..=i].iter().map(|c| -c).collect());
+        if err <= 0.0 {
+            break;
+        }
+    }
+    results
+}
+
+fn quantize_coefficients(lp: &[f64], precision: u32) -> Option<(Vec<i32>, i32)> {
+    let cmax = lp.iter().fold(0.0f64, |m, c| m.max(c.abs()));
+    if !cmax.is_finite() || cmax <= 0.0 {
+        return None;
+    }
+    let qmax = (1i32 << (precision - 1)) - 1;
+    let qmin = -(1i32 << (precision - 1));
+    let shift = (precision as i32 - 2 - cmax.log2().floor() as i32).min(MAX_LPC_SHIFT);
+    if shift < 0 {
+        return None;
+    }
+
+    let mut error = 0.0;
+    let mut coefs = Vec::with_capacity(lp.len());
+    for c in lp {
+        error += c * (1 << shift) as f64;
+        let q = (error.round() as i32).max(qmin).min(qmax);
+        error -= q as f64;
+        coefs.push(q);
+    }
+    if coefs.iter().all(|c| *c == 0) {
+        None
+    } else {
+        Some((coefs, shift))
+    }
+}
+
+fn plan_residual(residual: &[i32], order: usize, settings: &EncoderSettings) -> Option<ResidualPlan> {
+    let cost = &settings.cost_model;
+    let n = residual.len();
+    let mut best: Option<ResidualPlan> = None;
+
+    for partition_order in 0..=MAX_PARTITION_ORDER {
+        let num_partitions = 1 << partition_order;
+        if n % num_partitions != 0 || n / num_partitions <= order {
+            break;
+        }
+        let len = n / num_partitions;
+
+        let mut params = Vec::with_capacity(num_partitions);
+        let mut bits = 2 + 4;
+        let mut cycles = 0;
+        let mut wide_params = false;
+        for i in 0..num_partitions {
+            let start = if i == 0 { order } else { i * len };
+            let (param, part_bits, part_cycles) =
+                plan_partition(&residual[start..(i + 1) * len], settings)?;
+            if let ResidualParam::Rice(k) = param {
+                wide_params |= k >= RICE_ESCAPE;
+            }
+            params.push(param);
+            bits += part_bits;
+            cycles += cost.partition_overhead + part_cycles;
+        }
+        bits += num_partitions * if wide_params { RICE2_PARAM_BITS } else { RICE_PARAM_BITS } as usize;
+
+        let is_better = best
+            .as_ref()
+            .map(|b| score(bits, cycles, settings) < score(b.bits, b.cycles, settings))
+            .unwrap_or(true);
+        if is_better {
+            best = Some(ResidualPlan {
+                partition_order,
+                wide_params,
+                params,
+                bits,
+                cycles,
+            });
+        }
+    }
+
+    best
+}
+
+/// the cheapest way to code one partition, not counting its parameter field.
+fn plan_partition(values: &[i32], settings: &EncoderSettings) -> Option<(ResidualParam, usize, u32)> {
+    let cost = &settings.cost_model;
+    let n = values.len();
+    let folded: Vec<u64> = values.iter().map(|x| zigzag(*x) as u64).collect();
+    let sum: u64 = folded.iter().sum();
+
+    let mut best: Option<(ResidualParam, usize, u32)> = None;
+    let mut consider = |param: ResidualParam, bits: usize, cycles: u32| {
+        let is_better = best
+            .as_ref()
+            .map(|(_, b, c)| score(bits, cycles, settings) < score(*b, *c, settings))
+            .unwrap_or(true);
+        if is_better {
+            best = Some((param, bits, cycles));
+        }
+    };
+
+    // optimal rice parameter is near log2 of the mean, so only try its neighbors exactly
+    let mean = sum / n.max(1) as u64;
+    let estimate = if mean == 0 { 0 } else { 63 - mean.leading_zeros() };
+    for k in estimate.saturating_sub(1)..=(estimate + 1).min(MAX_RICE2_PARAM) {
+        let quotients: u64 = folded.iter().map(|u| u >> k).sum();
+        let bits = quotients as usize + n * (1 + k as usize);
+        let cycles = n as u32 * cost.per_rice_sample
+            + (quotients.min(u32::MAX as u64) as u32).saturating_mul(cost.per_rice_quotient);
+        consider(ResidualParam::Rice(k), bits, cycles);
+    }
+
+    // zero-width escapes are legal FLAC, but SimpleFlac's sign extension can't handle them
+    let width = values
+        .iter()
+        .map(|x| {
+            let magnitude = if *x < 0 { !*x } else { *x };
+            33 - magnitude.leading_zeros()
+        })
+        .max()
+        .unwrap_or(1)
+        .max(1);
+    if width <= MAX_ESCAPE_BITS {
+        consider(
+            ResidualParam::Escape(width),
+            5 + n * width as usize,
+            n as u32 * cost.per_escaped_sample,
+        );
+    }
+
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::music::flac_check::check_flac;
+
+    /// deterministic noise, so a failure reproduces
+    fn noise(len: usize, seed: u32) -> Vec<i16> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                (state >> 16) as i16
+            })
+            .collect()
+    }
+
+    fn round_trip(samples: &[i16]) -> EncodedFlac {
+        let encoded = encode_flac(samples, &EncoderSettings::default()).unwrap();
+        let checked = check_flac(&encoded.bytes).unwrap();
+        assert_eq!(checked.sample_depth, SAMPLE_DEPTH);
+        assert_eq!(checked.frame_offsets.len(), encoded.frames.len());
+        let expected: Vec<i32> = samples.iter().map(|x| *x as i32).collect();
+        assert_eq!(&checked.samples[..samples.len()], &expected[..]);
+        assert!(checked.samples[samples.len()..].iter().all(|x| *x == 0));
+        encoded
+    }
+
+    #[test]
+    fn white_noise_is_verbatim() {
+        let encoded = round_trip(&noise(PLAYBUF_SIZE * 3, 1));
+        for frame in &encoded.frames {
+            assert!(matches!(frame.kind, SubframeKind::Verbatim), "{:?}", frame.kind);
+            assert_eq!(frame.wasted_bits, 0);
+        }
+    }
+
+    #[test]
+    fn smooth_signal_is_predicted() {
+        // not a multiple of PLAYBUF_SIZE, so the padded last frame gets exercised too
+        let samples: Vec<i16> = (0..PLAYBUF_SIZE * 3 + 100)
+            .map(|i| ((i as f64 * 0.02).sin() * 12000.0).round() as i16)
+            .collect();
+        let encoded = round_trip(&samples);
+        for frame in &encoded.frames {
+            assert!(
+                matches!(frame.kind, SubframeKind::Fixed(_) | SubframeKind::Lpc { .. }),
+                "{:?}",
+                frame.kind
+            );
+            assert!(frame.kind.order() > 0);
+        }
+    }
+
+    #[test]
+    fn low_zero_bits_are_wasted() {
+        let samples: Vec<i16> = (0..PLAYBUF_SIZE * 2)
+            .map(|i| ((i as f64 * 0.05).sin() * 100.0).round() as i16 * 0x100)
+            .collect();
+        let encoded = round_trip(&samples);
+        for frame in &encoded.frames {
+            assert_eq!(frame.wasted_bits, 8);
+        }
+    }
+
+    #[test]
+    fn loud_burst_gets_escaped_partition() {
+        // quiet hiss with a full-scale burst lined up with two order-4 partitions
+        let burst = PLAYBUF_SIZE / 2..PLAYBUF_SIZE / 2 + PLAYBUF_SIZE / 8;
+        let loud = noise(PLAYBUF_SIZE, 2);
+        let samples: Vec<i16> = noise(PLAYBUF_SIZE, 3)
+            .iter()
+            .enumerate()
+            .map(|(i, x)| if burst.contains(&i) { loud[i] } else { x >> 13 })
+            .collect();
+
+        let block: Vec<i32> = samples.iter().map(|x| *x as i32).collect();
+        let subframe = choose_subframe(&block, &EncoderSettings::default());
+        let plan = subframe.plan.as_ref().expect("burst should still be predicted");
+        assert!(plan
+            .params
+            .iter()
+            .any(|p| matches!(p, ResidualParam::Escape(bits) if *bits > 0)));
+
+        round_trip(&samples);
+    }
+}
@@ -0,0 +1,154 @@
+// Copyright (C) 2021 lifning, licensed under the GNU Affero General Public License version 3.
+
+//! What actually comes out of the GBA's speaker for a given mix buffer: `AudioDriver::mixer`'s
+//! split into FIFO A and B (carries between packed bytes and all), then the sound circuit's
+//! volume scaling, bias, 10-bit clipping, and PWM amplitude resolution.
+
+use std::error::Error;
+use std::path::Path;
+
+use flowergal_proj_config::sound_info::{PLAYBUF_SIZE, SAMPLE_RATE};
+
+use crate::music::flac_check::check_flac;
+
+#[derive(Clone, Debug)]
+pub struct OutputSettings {
+    /// SOUNDBIAS bits 0-9
+    pub bias: u16,
+    /// SOUNDBIAS bits 14-15: 0 = 9-bit PWM, 1 = 8-bit, 2 = 7-bit, 3 = 6-bit
+    pub amplitude_resolution: u8,
+    /// SOUNDCNT_H bits 2 and 3: 100% (true) or 50% (false)
+    pub dma_a_full_volume: bool,
+    pub dma_b_full_volume: bool,
+}
+
+/// as set up by `AudioDriver::initialize`
+impl Default for OutputSettings {
+    fn default() -> Self {
+        OutputSettings {
+            bias: 0x200,
+            amplitude_resolution: 0,
+            dma_a_full_volume: true,
+            dma_b_full_volume: true,
+        }
+    }
+}
+
+/// what a converted `.flac` or `.pcm8` from OUT_DIR leaves in the mix buffer when played alone.
+/// `SimpleFlac` overwrites the buffer with its decoded samples; `RawPcm8` adds each byte
+/// zero-extended and shifted up 8, which only comes out right because the split masks it back.
+pub fn mixer_input(path: impl AsRef<Path>) -> Result<Vec<i32>, Box<dyn Error>> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path)?;
+    match path.extension().and_then(|x| x.to_str()) {
+        Some("flac") => {
+            let checked = check_flac(&bytes).map_err(|e| format!("{}: {}", path.to_string_lossy(), e))?;
+            if checked.sample_depth != 16 {
+                return Err(format!("{}: expected 16-bit flac, got {}", path.to_string_lossy(), checked.sample_depth).into());
+            }
+            Ok(checked.samples)
+        }
+        Some("pcm8") => Ok(bytes.iter().map(|b| (*b as i32) << 8).collect()),
+        _ => Err(format!("{}: expected a .flac or .pcm8", path.to_string_lossy()).into()),
+    }
+}
+
+/// the bytes `AudioDriver::mixer` would hand to FIFO A and FIFO B.
+/// goes four samples at a time like the asm does, since B's ninth bits get added to a whole
+/// packed word of A, and a 0xFF byte plus its ninth bit carries into the next sample up.
+pub fn split_fifos(mix_buffer: &[i32]) -> (Vec<i8>, Vec<i8>) {
+    let mut fifo_a = Vec::with_capacity(mix_buffer.len());
+    let mut fifo_b = Vec::with_capacity(mix_buffer.len());
+    for quad in mix_buffer.chunks(4) {
+        let mut word_a = 0u32;
+        let mut ninth_bits = 0u32;
+        for (i, sample) in quad.iter().enumerate() {
+            word_a |= ((*sample as u32 >> 8) & 0xFF) << (i * 8);
+            ninth_bits |= ((*sample as u32 >> 7) & 1) << (i * 8);
+        }
+        let word_b = word_a.wrapping_add(ninth_bits);
+        for i in 0..quad.len() {
+            fifo_a.push((word_a >> (i * 8)) as u8 as i8);
+            fifo_b.push((word_b >> (i * 8)) as u8 as i8);
+        }
+    }
+    (fifo_a, fifo_b)
+}
+
+/// PWM duty level for one pair of FIFO samples, from 0 to `(0x400 >> (1 + resolution)) - 1`.
+/// each DMA channel goes into the 10-bit sum at 4x (100%) or 2x (50%), same as mGBA does it.
+pub fn pwm_level(a: i8, b: i8, settings: &OutputSettings) -> u16 {
+    let scale = |full: bool| if full { 4 } else { 2 };
+    let sum = a as i32 * scale(settings.dma_a_full_volume)
+        + b as i32 * scale(settings.dma_b_full_volume)
+        + settings.bias as i32;
+    let clipped = sum.max(0).min(0x3FF) as u16;
+    clipped >> (1 + settings.amplitude_resolution)
+}
+
+/// renders a stream of 16-bit mixer input the way the hardware would play it, as 16-bit PCM
+/// centered on the bias level (one output sample per input sample; the PWM carrier itself
+/// is well above anything we care to hear).
+pub fn simulate(mix_input: &[i32], settings: &OutputSettings) -> Vec<i16> {
+    let shift = 1 + settings.amplitude_resolution as u32;
+    let center = (settings.bias >> shift) as i32;
+    let mut output = Vec::with_capacity(mix_input.len());
+    // the mixer runs a frame at a time, which matters for where the carries land
+    for frame in mix_input.chunks(PLAYBUF_SIZE) {
+        let (fifo_a, fifo_b) = split_fifos(frame);
+        for (a, b) in fifo_a.iter().zip(fifo_b.iter()) {
+            let level = pwm_level(*a, *b, settings) as i32;
+            output.push(((level - center) << (6 + shift)).max(i16::MIN as i32).min(i16::MAX as i32) as i16);
+        }
+    }
+    output
+}
+
+/// convenience for the command line: converted sound in, what the speaker plays out as a WAV
+pub fn render_to_wav(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    settings: &OutputSettings,
+) -> Result<(), Box<dyn Error>> {
+    let samples = simulate(&mixer_input(input)?, settings);
+    crate::music::wav::write_wav_mono16(output, SAMPLE_RATE as u32, &samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::music::flac_encoder::{encode_flac, EncoderSettings};
+
+    #[test]
+    fn split_matches_mixer_asm() {
+        let mix = [
+            0x0180, // ninth bit set: B rounds up
+            -0x0100, // negative, no ninth bit
+            -0x0080, // 0xFF plus its ninth bit wraps to 0x00...
+            0x7F00, // ...and carries into the next sample's B
+        ];
+        let (a, b) = split_fifos(&mix);
+        assert_eq!(a, vec![1, -1, -1, 127]);
+        assert_eq!(b, vec![2, -1, 0, -128]);
+    }
+
+    #[test]
+    fn flac_round_trip_plays_back_exactly() {
+        // 9-bit samples, staying clear of -0x80 so nothing carries
+        let samples: Vec<i16> = (0..PLAYBUF_SIZE * 3)
+            .map(|i| {
+                let v = ((i as f64 * 0.05).sin() * 100.0).round() as i16 * 0x80;
+                if v == -0x80 { -0x100 } else { v }
+            })
+            .collect();
+        let encoded = encode_flac(&samples, &EncoderSettings::default()).unwrap();
+        let checked = check_flac(&encoded.bytes).unwrap();
+        assert_eq!(checked.sample_depth, 16);
+        assert_eq!(&checked.samples[..samples.len()], &samples.iter().map(|x| *x as i32).collect::<Vec<_>>()[..]);
+
+        // both FIFOs at 100% add up to twice the input's scale
+        let played = simulate(&checked.samples, &OutputSettings::default());
+        let expected: Vec<i16> = samples.iter().map(|x| x * 2).collect();
+        assert_eq!(&played[..samples.len()], &expected[..]);
+    }
+}
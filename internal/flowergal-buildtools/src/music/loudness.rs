@@ -0,0 +1,168 @@
+// Copyright (C) 2021 lifning, licensed under the GNU Affero General Public License version 3.
+
+//! ITU-R BS.1770-4 integrated loudness and (4x oversampled) true peak, for mono signals.
+
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+const BLOCK_SECONDS: f64 = 0.4;
+/// 75% overlap between gating blocks
+const STEP_SECONDS: f64 = 0.1;
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+const OVERSAMPLING: usize = 4;
+/// per phase of the interpolation filter
+const INTERPOLATION_TAPS: usize = 12;
+
+/// what we measured about a source, and what we did about it
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub struct Levels {
+    /// `None` if the whole thing got gated out as silence
+    pub integrated_lufs: Option<f64>,
+    pub true_peak_dbtp: f64,
+    pub applied_gain_db: f64,
+}
+
+pub struct Loudness {
+    pub integrated_lufs: Option<f64>,
+    pub true_peak_dbtp: f64,
+}
+
+pub fn measure(samples: &[i16], sample_rate: u32) -> Loudness {
+    let normalized: Vec<f64> = samples.iter().map(|x| *x as f64 / 32768.0).collect();
+    Loudness {
+        integrated_lufs: integrated_loudness(&normalized, sample_rate as f64),
+        true_peak_dbtp: 20.0 * true_peak(&normalized).log10(),
+    }
+}
+
+fn integrated_loudness(samples: &[f64], sample_rate: f64) -> Option<f64> {
+    let weighted = k_weight(samples, sample_rate);
+    let block_len = (BLOCK_SECONDS * sample_rate).round() as usize;
+    let step = (STEP_SECONDS * sample_rate).round() as usize;
+
+    let mean_squares: Vec<f64> = if weighted.len() < block_len {
+        // too short to gate properly (e.g. a short sfx): just take the whole thing
+        vec![mean_square(&weighted)]
+    } else {
+        (0..=(weighted.len() - block_len) / step)
+            .map(|i| mean_square(&weighted[i * step..i * step + block_len]))
+            .collect()
+    };
+
+    let above_absolute: Vec<f64> = mean_squares
+        .iter()
+        .copied()
+        .filter(|z| loudness_of(*z) > ABSOLUTE_GATE_LUFS)
+        .collect();
+    if above_absolute.is_empty() {
+        return None;
+    }
+    let relative_gate = loudness_of(mean(&above_absolute)) + RELATIVE_GATE_LU;
+    let above_relative: Vec<f64> = above_absolute
+        .iter()
+        .copied()
+        .filter(|z| loudness_of(*z) > relative_gate)
+        .collect();
+    Some(loudness_of(mean(&above_relative)))
+}
+
+fn loudness_of(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+fn mean(x: &[f64]) -> f64 {
+    x.iter().sum::<f64>() / x.len() as f64
+}
+
+fn mean_square(x: &[f64]) -> f64 {
+    x.iter().map(|s| s * s).sum::<f64>() / x.len().max(1) as f64
+}
+
+/// the two-stage "K" pre-filter: a high shelf for the head's acoustic effect, then a high-pass.
+/// coefficients are derived for our sample rate rather than using the spec's 48kHz tables.
+fn k_weight(samples: &[f64], sample_rate: f64) -> Vec<f64> {
+    let shelf = {
+        let f0 = 1681.974450955533;
+        let gain_db = 3.999843853973347;
+        let q = 0.7071752369554196;
+        let k = (PI * f0 / sample_rate).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        Biquad {
+            b: [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        }
+    };
+    let high_pass = {
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+        let k = (PI * f0 / sample_rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        Biquad {
+            b: [1.0, -2.0, 1.0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        }
+    };
+    high_pass.filter(&shelf.filter(samples))
+}
+
+struct Biquad {
+    b: [f64; 3],
+    /// a1, a2 (a0 normalized to 1)
+    a: [f64; 2],
+}
+
+impl Biquad {
+    fn filter(&self, input: &[f64]) -> Vec<f64> {
+        let (mut x1, mut x2, mut y1, mut y2) = (0.0, 0.0, 0.0, 0.0);
+        input
+            .iter()
+            .map(|x| {
+                let y = self.b[0] * x + self.b[1] * x1 + self.b[2] * x2 - self.a[0] * y1 - self.a[1] * y2;
+                x2 = x1;
+                x1 = *x;
+                y2 = y1;
+                y1 = y;
+                y
+            })
+            .collect()
+    }
+}
+
+/// peak magnitude including inter-sample peaks, found by windowed-sinc interpolation
+fn true_peak(samples: &[f64]) -> f64 {
+    let half = INTERPOLATION_TAPS / 2;
+    let filters: Vec<Vec<f64>> = (1..OVERSAMPLING)
+        .map(|phase| {
+            (0..INTERPOLATION_TAPS)
+                .map(|tap| {
+                    // distance from the interpolated point to sample `n - half + 1 + tap`, in samples
+                    let t = tap as f64 - half as f64 + 1.0 - phase as f64 / OVERSAMPLING as f64;
+                    let sinc = if t == 0.0 { 1.0 } else { (PI * t).sin() / (PI * t) };
+                    // hann window over the filter's span
+                    let w = 0.5 + 0.5 * (PI * t / (half as f64 + 1.0)).cos();
+                    sinc * w
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut peak = samples.iter().fold(0.0f64, |acc, x| acc.max(x.abs()));
+    for n in 0..samples.len() {
+        for filter in filters.iter() {
+            let mut y = 0.0;
+            for (tap, coef) in filter.iter().enumerate() {
+                let index = n as isize - half as isize + 1 + tap as isize;
+                if index >= 0 && (index as usize) < samples.len() {
+                    y += coef * samples[index as usize];
+                }
+            }
+            peak = peak.max(y.abs());
+        }
+    }
+    peak
+}
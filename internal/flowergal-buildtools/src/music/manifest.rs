@@ -0,0 +1,145 @@
+// Copyright (C) 2021 lifning, licensed under the GNU Affero General Public License version 3.
+
+//! `assets/sound_manifest.toml`: what sounds exist and how to convert each of them.
+//! (flowergal-proj-config's build script reads the same file for the `MusicId`/`SfxId` enums.)
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+pub const ASSETS_DIR: &str = "../../assets";
+pub const SOUND_MANIFEST: &str = "../../assets/sound_manifest.toml";
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SoundManifest {
+    #[serde(default)]
+    pub project: ProjectSettings,
+    #[serde(default)]
+    pub music: Vec<SoundEntry>,
+    #[serde(default)]
+    pub sfx: Vec<SoundEntry>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectSettings {
+    /// integrated loudness (BS.1770) to bring every normalized sound to
+    #[serde(default)]
+    pub target_loudness_lufs: Option<f64>,
+    /// normalization gain gets reduced to keep true peaks at or below this
+    #[serde(default)]
+    pub max_true_peak_dbtp: Option<f64>,
+    /// fail the build if all the converted audio together takes more ROM than this
+    #[serde(default)]
+    pub audio_rom_budget_bytes: Option<u64>,
+    /// fail the build if any frame of any sound is predicted to take more than this
+    /// percentage of a video frame's cycles to decode
+    #[serde(default)]
+    pub max_decode_percent: Option<f64>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SoundEntry {
+    pub id: String,
+    /// shown in the jukebox instead of the id (only read by flowergal-proj-config)
+    #[serde(default)]
+    pub title: Option<String>,
+    /// relative to ASSETS_DIR
+    pub source: String,
+    /// youtube video id to fetch `source` from if it's missing
+    #[serde(default)]
+    pub download: Option<String>,
+    pub codec: Codec,
+    #[serde(default)]
+    pub quality: QualityPreset,
+    /// whether to apply the project's loudness target to this one
+    #[serde(default = "default_true")]
+    pub normalize: bool,
+    /// on top of normalization, if any
+    #[serde(default)]
+    pub gain_db: f64,
+    /// in seconds of the source
+    #[serde(default)]
+    pub loop_start: Option<f64>,
+    /// in seconds of the source
+    #[serde(default)]
+    pub loop_end: Option<f64>,
+    /// cut near-silence off both ends (only for sounds that don't loop).
+    /// `load_manifest` fills this in: on by default for sfx, off for music.
+    #[serde(default)]
+    pub trim_silence: Option<bool>,
+}
+
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Codec {
+    Flac,
+    RawPcm8,
+}
+
+/// how aggressively the build throws away low bits of each song before FLAC encoding.
+/// everything below the mixer's resolution goes regardless; these decide how much more can go.
+#[derive(Deserialize, Copy, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityPreset {
+    High,
+    Standard,
+    Portable,
+}
+
+impl Default for QualityPreset {
+    fn default() -> Self {
+        QualityPreset::Standard
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl SoundEntry {
+    pub fn source_path(&self) -> PathBuf {
+        Path::new(ASSETS_DIR).join(&self.source)
+    }
+
+    pub fn trims_silence(&self) -> bool {
+        self.trim_silence.unwrap_or(false) && self.loop_start.is_none()
+    }
+}
+
+pub fn load_manifest() -> Result<SoundManifest, Box<dyn Error>> {
+    println!("cargo:rerun-if-changed={}", SOUND_MANIFEST);
+    let text = std::fs::read_to_string(SOUND_MANIFEST)?;
+    let mut manifest: SoundManifest =
+        toml::from_str(&text).map_err(|e| format!("{}: {}", SOUND_MANIFEST, e))?;
+
+    for entry in manifest.music.iter().chain(manifest.sfx.iter()) {
+        if let (Some(start), Some(end)) = (entry.loop_start, entry.loop_end) {
+            if end <= start {
+                return Err(format!(
+                    "{}: loop_end ({}) must come after loop_start ({})",
+                    entry.id, end, start
+                ).into());
+            }
+        }
+        if entry.loop_start.map_or(false, |x| x < 0.0) {
+            return Err(format!("{}: negative loop_start", entry.id).into());
+        }
+    }
+
+    for entry in manifest.sfx.iter_mut() {
+        // SimpleFlac writes over the mix buffer rather than adding to it, so only the bgm can be one
+        if entry.codec != Codec::RawPcm8 {
+            return Err(format!("{}: sfx must use codec = \"raw_pcm8\"", entry.id).into());
+        }
+        entry.trim_silence.get_or_insert(true);
+    }
+    for entry in manifest.music.iter_mut() {
+        entry.trim_silence.get_or_insert(false);
+    }
+
+    Ok(manifest)
+}
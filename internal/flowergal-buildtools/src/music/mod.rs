@@ -1 +1,11 @@
+pub mod bit_reduction;
+pub mod bitstream;
+pub mod cache;
+pub mod flac_check;
+pub mod flac_encoder;
+pub mod gba_output;
+pub mod loudness;
+pub mod manifest;
 pub mod pcm_conv;
+pub mod rom_report;
+pub mod wav;
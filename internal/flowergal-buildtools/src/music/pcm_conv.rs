@@ -1,16 +1,27 @@
 // Copyright (C) 2021 lifning, licensed under the GNU Affero General Public License version 3.
 
-use flowergal_proj_config::sound_info;
-use build_const::ConstWriter;
+use flowergal_proj_config::resources::LoopPoint;
+use flowergal_proj_config::sound_info::{self, PLAYBUF_SIZE};
+use build_const::{ConstValueWriter, ConstWriter};
+use crate::music::bit_reduction::{self, BitReductionSettings};
+use crate::music::cache::{self, ConvertedSound};
+use crate::music::flac_check::{self, CheckedFlac};
+use crate::music::flac_encoder::{self, EncoderSettings};
+use crate::music::loudness::{self, Levels};
+use crate::music::manifest::{self, Codec, ProjectSettings, SoundEntry};
+use crate::music::rom_report::{self, SoundReport};
+use crate::music::wav;
 use itertools::Itertools;
 use rayon::prelude::*;
 use std::error::Error;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use std::process::{Command, Output};
 
-const MP3_DIR: &str = "../../assets/mp3";
-const LOSSY_WAV: &str = "../../external/lossywav/lossyWAV";
-const FLAC_MOD: &str = "../../external/flac/src/flac/flac";
+/// the raw PCM mixer asm works in groups of eight samples
+const RAW_PCM_BLOCK: usize = 8;
+
+/// about -60 dBFS; anything quieter at the ends of a trimmed sound gets cut
+const SILENCE_THRESHOLD: i16 = 33;
 
 trait CommandSuccess {
     fn actually_run(&mut self) -> Result<Output, Box<dyn Error>>;
@@ -46,101 +57,315 @@ impl CommandSuccess for Command {
 }
 
 pub fn convert_songs_and_sfx() -> Result<(), Box<dyn Error>> {
+    let manifest = manifest::load_manifest()?;
     let mut bc_out = ConstWriter::for_build("sound_data_bc")?.finish_dependencies();
 
-    Command::new("make")
-        .arg("-j4")
-        .current_dir(Path::new(LOSSY_WAV).parent().unwrap())
-        .actually_run()?;
-
-    if !Path::new(MP3_DIR).exists() {
-        std::fs::create_dir_all(MP3_DIR)?;
+    for entry in manifest.music.iter().chain(manifest.sfx.iter()) {
+        download_if_missing(entry)?;
     }
 
-    Command::new("youtube-dl")
-        .args(&["--ignore-config", "--download-archive", "downloaded-ids.txt", "32ZTjFW2RYo"])
-        .current_dir(Path::new(MP3_DIR))
-        .actually_run()?;
+    let music = convert_all(&manifest.music, &manifest.project, "music")?;
+    add_sound_array(&mut bc_out, "MUSIC_DATA", &music);
+    let sfx = convert_all(&manifest.sfx, &manifest.project, "sfx")?;
+    add_sound_array(&mut bc_out, "SFX_DATA", &sfx);
 
-    if !Path::new(FLAC_MOD).exists() {
-        let flac_dir = Path::new(FLAC_MOD).parent().unwrap().parent().unwrap().parent().unwrap();
-        if !flac_dir.join("Makefile").exists() {
-            Command::new("sh")
-                .arg("autogen.sh")
-                .current_dir(flac_dir)
-                .actually_run()?;
-            Command::new("sh")
-                .args(&["configure", "-C"])
-                .current_dir(flac_dir)
-                .actually_run()?;
-        }
-        Command::new("make")
-            .arg("-j4")
-            .current_dir(flac_dir)
+    let reports: Vec<SoundReport> = music.into_iter().chain(sfx).map(|(_, report)| report).collect();
+    rom_report::report(&reports, &manifest.project)
+}
+
+fn add_sound_array(bc_out: &mut ConstValueWriter, name: &str, sounds: &[(String, SoundReport)]) {
+    if sounds.is_empty() {
+        // build_const refuses to write zero-length arrays
+        bc_out.add_value_raw(name, "[Sound; 0]", "[]");
+    } else {
+        let sounds_ref: Vec<&str> = sounds.iter().map(|(init, _)| init.as_str()).collect();
+        bc_out.add_array_raw(name, "Sound", &sounds_ref);
+    }
+}
+
+fn download_if_missing(entry: &SoundEntry) -> Result<(), Box<dyn Error>> {
+    let source_path = entry.source_path();
+    if let (false, Some(video_id)) = (source_path.exists(), &entry.download) {
+        let dir = source_path.parent().unwrap();
+        std::fs::create_dir_all(dir)?;
+        Command::new("youtube-dl")
+            .args(&["--ignore-config", video_id])
+            .current_dir(dir)
             .actually_run()?;
     }
+    Ok(())
+}
 
-    let vec: Vec<String> = sound_info::SONG_FILES
+/// returns the `Sound` initializer for each entry, in order, along with what it costs
+fn convert_all(
+    entries: &[SoundEntry],
+    project: &ProjectSettings,
+    kind: &'static str,
+) -> Result<Vec<(String, SoundReport)>, Box<dyn Error>> {
+    let converted: Result<Vec<(String, SoundReport)>, String> = entries
         .par_iter()
-        .map(|mp3_name| {
-            let mp3_path = Path::new(MP3_DIR).join(mp3_name);
-            let flac_path = convert_mp3_to_flac(mp3_path).unwrap();
-            format!(
-                "Sound::Flac(include_bytes_align_as!(u32, \"{}\"))",
-                flac_path.to_string_lossy()
-            )
-        })
+        .map(|entry| convert_sound(entry, project, kind).map_err(|e| format!("{}: {}", entry.id, e)))
         .collect();
-    let vec_ref: Vec<&str> = vec.iter().map(String::as_str).collect();
-    bc_out.add_array_raw("MUSIC_DATA", "Sound", &vec_ref);
+    Ok(converted?)
+}
 
-    Ok(())
+fn convert_sound(
+    entry: &SoundEntry,
+    project: &ProjectSettings,
+    kind: &'static str,
+) -> Result<(String, SoundReport), Box<dyn Error>> {
+    let source_path = entry.source_path();
+    if !source_path.is_file() {
+        return Err(format!("source {} not found", source_path.to_string_lossy()).into());
+    }
+    println!("cargo:rerun-if-changed={}", source_path.to_string_lossy());
+
+    let key = cache::cache_key(entry, project, &std::fs::read(&source_path)?);
+    let converted = match cache::load(&key)? {
+        Some(converted) => converted,
+        None => {
+            let converted = convert_uncached(entry, project, &source_path)?;
+            cache::store(&key, &converted)?;
+            converted
+        }
+    };
+
+    if let Some(short_db) = peak_limited_by(entry, project, &converted.levels) {
+        println!(
+            "cargo:warning={}: held {:.1} dB short of the loudness target by the true peak ceiling",
+            entry.id, short_db
+        );
+    }
+
+    let (variant, extension) = match entry.codec {
+        Codec::Flac => ("Flac", "flac"),
+        Codec::RawPcm8 => ("RawPcm8", "pcm8"),
+    };
+    let out_path = Path::new(&std::env::var("OUT_DIR")?)
+        .join(&entry.id)
+        .with_extension(extension);
+    std::fs::write(&out_path, &converted.data)?;
+    let init = format!(
+        "Sound::{}(include_bytes_align_as!(u32, {:?}), {:?})",
+        variant,
+        out_path.to_string_lossy(),
+        converted.loop_point
+    );
+    Ok((init, rom_report::measure(entry, kind, &converted.data, &converted.levels)?))
 }
 
-fn convert_mp3_to_flac(mp3_path: impl AsRef<Path>) -> Result<PathBuf, Box<dyn Error>> {
-    let mp3_path = mp3_path.as_ref();
+fn convert_uncached(
+    entry: &SoundEntry,
+    project: &ProjectSettings,
+    source_path: &Path,
+) -> Result<ConvertedSound, Box<dyn Error>> {
+    let mut samples = decode_to_pcm(source_path)?;
 
-    let temp_dir = tempfile::tempdir()?;
-    let wav_path = temp_dir.path().join("temp_flac_conv").with_extension("wav");
-    let lossywav_path = wav_path.with_extension("lossy.wav");
+    let block_size = match entry.codec {
+        Codec::Flac => PLAYBUF_SIZE,
+        Codec::RawPcm8 => RAW_PCM_BLOCK,
+    };
+    // loop points are in the source's time, so this comes before any trimming
+    let loop_start = snap_loop_points(entry, &mut samples, block_size)?;
+    if entry.trims_silence() {
+        // before measuring loudness, so a sound's silent tail doesn't count against it.
+        // (only sounds that don't loop get trimmed, so there's no loop_start to shift.)
+        trim_silence(&mut samples);
+    }
+
+    let levels = choose_gain(entry, project, &samples);
+    apply_gain(&mut samples, levels.applied_gain_db);
 
-    let flac_path =
-        Path::new(&std::env::var("OUT_DIR")?)
-            .join(mp3_path.file_name().ok_or("uh oh")?)
-            .with_extension("flac");
-    if flac_path.is_file() {
-        return Ok(flac_path);
+    match entry.codec {
+        Codec::Flac => {
+            let settings = BitReductionSettings::from(entry.quality);
+            let reduced = bit_reduction::reduce_bit_depth(&samples, &settings);
+            let encoded = flac_encoder::encode_flac(&reduced.samples, &EncoderSettings::default())?;
+            let checked = check_flac_output(&encoded.bytes, &reduced.samples)?;
+            let loop_point = loop_start.map(|sample| LoopPoint {
+                sample: sample as u32,
+                byte_offset: checked.frame_offsets[sample / PLAYBUF_SIZE] as u32,
+            });
+            Ok(ConvertedSound {
+                data: encoded.bytes,
+                loop_point,
+                levels,
+            })
+        }
+        Codec::RawPcm8 => {
+            let mut pcm: Vec<u8> = samples.iter().map(|x| to_pcm8(*x) as u8).collect();
+            while pcm.len() % RAW_PCM_BLOCK != 0 {
+                pcm.push(0);
+            }
+            let loop_point = loop_start.map(|sample| LoopPoint {
+                sample: sample as u32,
+                byte_offset: sample as u32,
+            });
+            Ok(ConvertedSound {
+                data: pcm,
+                loop_point,
+                levels,
+            })
+        }
     }
+}
+
+/// make sure what we wrote is something SimpleFlac can play, and that it plays back exactly
+/// what we asked the encoder for (plus the silence it pads the last frame with)
+fn check_flac_output(bytes: &[u8], expected: &[i16]) -> Result<CheckedFlac, Box<dyn Error>> {
+    let checked = flac_check::check_flac(bytes)
+        .map_err(|e| format!("encoded FLAC fails the runtime conformance check: {}", e))?;
+    let expected_padded = expected.iter().map(|x| *x as i32).chain(std::iter::repeat(0));
+    if let Some((i, (actual, wanted))) = checked
+        .samples
+        .iter()
+        .zip(expected_padded)
+        .enumerate()
+        .find(|(_, (actual, wanted))| **actual != *wanted)
+    {
+        return Err(format!(
+            "encoded FLAC decodes to {} instead of {} at sample {} (frame {})",
+            actual,
+            wanted,
+            i,
+            i / PLAYBUF_SIZE
+        ).into());
+    }
+    Ok(checked)
+}
+
+/// anything ffmpeg can read, as mono 16-bit at our output sample rate
+fn decode_to_pcm(source_path: &Path) -> Result<Vec<i16>, Box<dyn Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let wav_path = temp_dir.path().join("temp_pcm_conv").with_extension("wav");
 
-    println!("cargo:rerun-if-changed={}", mp3_path.to_string_lossy());
     Command::new("ffmpeg")
         .args(&["-loglevel", "quiet", "-y", "-i"])
-        .arg(mp3_path)
+        .arg(source_path)
         .args(&["-ac", "1", "-ar"])
         .arg(format!("{}", sound_info::SAMPLE_RATE))
+        .args(&["-c:a", "pcm_s16le"])
         .arg(&wav_path)
         .actually_run()?;
 
-    if let Err(e) = Command::new(LOSSY_WAV)
-        .arg(&wav_path)
-        .args(&["--force", "--silent", "--shaping", "fixed", "--outdir"])
-        .arg(lossywav_path.parent().unwrap())
-        .actually_run() {
+    let wav = wav::read_wav_mono16(&wav_path)?;
+    if wav.sample_rate != sound_info::SAMPLE_RATE as u32 {
         std::mem::forget(temp_dir);
-        return Err(e);
+        return Err(format!(
+            "{}: expected {}Hz, got {}Hz",
+            wav_path.to_string_lossy(),
+            sound_info::SAMPLE_RATE,
+            wav.sample_rate
+        ).into());
     }
-    std::fs::remove_file(wav_path)?;
+    Ok(wav.samples)
+}
 
-    if let Err(e) = Command::new(FLAC_MOD)
-        .args(&["--silent", "-f", "-8", "--escape-coding", "--rice-partition-order=8", "--max-lpc-order=4",])
-        .arg(format!("--blocksize={}", sound_info::PLAYBUF_SIZE))
-        .arg(format!("--output-name={}", flac_path.to_string_lossy()))
-        .arg(&lossywav_path)
-        .actually_run() {
-        std::mem::forget(temp_dir);
-        return Err(e);
+fn trim_silence(samples: &mut Vec<i16>) {
+    let audible = |x: &i16| (*x as i32).abs() >= SILENCE_THRESHOLD as i32;
+    let end = samples.iter().rposition(audible).map_or(0, |i| i + 1);
+    samples.truncate(end);
+    let start = samples.iter().position(audible).unwrap_or(0);
+    samples.drain(..start);
+}
+
+/// normalize to the project's loudness target (within its true peak limit), then add the
+/// entry's own gain on top
+fn choose_gain(entry: &SoundEntry, project: &ProjectSettings, samples: &[i16]) -> Levels {
+    let measured = loudness::measure(samples, sound_info::SAMPLE_RATE as u32);
+    let mut gain_db = 0.0;
+    if let (true, Some(target), Some(lufs)) =
+        (entry.normalize, project.target_loudness_lufs, measured.integrated_lufs)
+    {
+        gain_db = target - lufs;
+        if let Some(max_peak) = project.max_true_peak_dbtp {
+            gain_db = gain_db.min(max_peak - measured.true_peak_dbtp);
+        }
+    }
+    Levels {
+        integrated_lufs: measured.integrated_lufs,
+        true_peak_dbtp: measured.true_peak_dbtp,
+        applied_gain_db: gain_db + entry.gain_db,
+    }
+}
+
+/// how far short of the loudness target `choose_gain` stopped to stay under the true peak
+/// ceiling, if it had to
+fn peak_limited_by(entry: &SoundEntry, project: &ProjectSettings, levels: &Levels) -> Option<f64> {
+    let target = project.target_loudness_lufs?;
+    let lufs = levels.integrated_lufs?;
+    if !entry.normalize {
+        return None;
+    }
+    let short_db = (target - lufs) - (levels.applied_gain_db - entry.gain_db);
+    if short_db > 0.05 {
+        Some(short_db)
+    } else {
+        None
+    }
+}
+
+fn apply_gain(samples: &mut [i16], gain_db: f64) {
+    if gain_db == 0.0 {
+        return;
+    }
+    let gain = 10f64.powf(gain_db / 20.0);
+    for x in samples.iter_mut() {
+        *x = (*x as f64 * gain).round().max(i16::MIN as f64).min(i16::MAX as f64) as i16;
+    }
+}
+
+fn to_pcm8(sample: i16) -> i8 {
+    ((sample as i32 + 0x80) >> 8).min(i8::MAX as i32) as i8
+}
+
+fn seconds_to_samples(seconds: f64) -> usize {
+    (seconds * sound_info::SAMPLE_RATE as f64).round() as usize
+}
+
+/// cuts the sound off at `loop_end`, and if it loops, pads silence in front so `loop_start`
+/// lands on a block boundary and rounds the loop's length to a whole number of blocks, since
+/// the decoders can only jump back to the start of a block.
+/// returns the sample to loop back to, if any.
+fn snap_loop_points(
+    entry: &SoundEntry,
+    samples: &mut Vec<i16>,
+    block_size: usize,
+) -> Result<Option<usize>, Box<dyn Error>> {
+    let mut end = match entry.loop_end {
+        Some(seconds) => seconds_to_samples(seconds),
+        None => samples.len(),
+    };
+    if end > samples.len() {
+        return Err(format!("loop_end is past the end of the source ({} samples)", samples.len()).into());
+    }
+
+    let mut start = match entry.loop_start {
+        Some(seconds) => seconds_to_samples(seconds),
+        None => {
+            samples.truncate(end);
+            return Ok(None);
+        }
+    };
+    if start >= end {
+        return Err(format!("loop_start is past the end of the sound ({} samples)", end).into());
+    }
+
+    let lead_in = (block_size - start % block_size) % block_size;
+    samples.splice(0..0, std::iter::repeat(0).take(lead_in));
+    start += lead_in;
+    end += lead_in;
+
+    let length = end - start;
+    let snapped_length = ((length + block_size / 2) / block_size).max(1) * block_size;
+    if snapped_length != length {
+        println!(
+            "cargo:warning={}: loop length rounded from {} to {} samples",
+            entry.id, length, snapped_length
+        );
     }
-    std::fs::remove_file(lossywav_path)?;
+    // if we're rounding up, prefer whatever the source has after loop_end over silence
+    samples.resize(start + snapped_length, 0);
 
-    Ok(flac_path)
+    Ok(Some(start))
 }
@@ -0,0 +1,160 @@
+// Copyright (C) 2021 lifning, licensed under the GNU Affero General Public License version 3.
+
+//! How much ROM and CPU each converted sound costs, printed with the build output and written
+//! to `target/sound_report.toml` for anything that wants to track it over time.
+
+use std::error::Error;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use flowergal_proj_config::sound_info::{CYCLES_PER_FRAME, PLAYBUF_SIZE, SAMPLE_RATE};
+
+use crate::music::flac_check;
+use crate::music::loudness::Levels;
+use crate::music::manifest::{Codec, ProjectSettings, SoundEntry};
+
+/// `rom` in linker_script.ld
+pub const ROM_SIZE: u64 = 32 << 20;
+
+/// `RawPcm8::mix_into` does eight samples per ldmia/stmia round trip; ballpark like the
+/// figures in `DecodeCostModel`.
+const RAW_PCM_CYCLES_PER_SAMPLE: u32 = 5;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SoundReport {
+    pub id: String,
+    pub kind: &'static str,
+    pub codec: &'static str,
+    pub duration_seconds: f64,
+    pub encoded_bytes: u64,
+    pub bits_per_sample: f64,
+    /// worst case over all of the sound's frames
+    pub max_decode_cycles: u32,
+    pub max_decode_percent: f64,
+    /// as measured at the source, before any gain.  `None` if it's all silence
+    pub integrated_lufs: Option<f64>,
+    pub true_peak_dbtp: f64,
+    pub applied_gain_db: f64,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    total_bytes: u64,
+    rom_size: u64,
+    audio_rom_budget_bytes: Option<u64>,
+    max_decode_percent: Option<f64>,
+    sounds: &'a [SoundReport],
+}
+
+fn decode_percent(cycles: u32) -> f64 {
+    100.0 * cycles as f64 / CYCLES_PER_FRAME as f64
+}
+
+pub fn measure(
+    entry: &SoundEntry,
+    kind: &'static str,
+    data: &[u8],
+    levels: &Levels,
+) -> Result<SoundReport, Box<dyn Error>> {
+    let (codec, samples, max_decode_cycles) = match entry.codec {
+        Codec::Flac => {
+            let checked = flac_check::check_flac(data)?;
+            let worst = checked.frame_cycles.iter().copied().max().unwrap_or(0);
+            ("flac", checked.samples.len(), worst)
+        }
+        Codec::RawPcm8 => ("raw_pcm8", data.len(), PLAYBUF_SIZE as u32 * RAW_PCM_CYCLES_PER_SAMPLE),
+    };
+    Ok(SoundReport {
+        id: entry.id.clone(),
+        kind,
+        codec,
+        duration_seconds: samples as f64 / SAMPLE_RATE as f64,
+        encoded_bytes: data.len() as u64,
+        bits_per_sample: 8.0 * data.len() as f64 / samples.max(1) as f64,
+        max_decode_cycles,
+        max_decode_percent: decode_percent(max_decode_cycles),
+        integrated_lufs: levels.integrated_lufs,
+        true_peak_dbtp: levels.true_peak_dbtp,
+        applied_gain_db: levels.applied_gain_db,
+    })
+}
+
+fn report_path() -> PathBuf {
+    let target_dir = std::env::var("CARGO_TARGET_DIR").unwrap_or_else(|_| "../../target".to_string());
+    PathBuf::from(target_dir).join("sound_report.toml")
+}
+
+/// prints and writes the report, then fails if anything's over budget
+pub fn report(sounds: &[SoundReport], project: &ProjectSettings) -> Result<(), Box<dyn Error>> {
+    let total_bytes: u64 = sounds.iter().map(|s| s.encoded_bytes).sum();
+
+    println!(
+        "{:<24} {:<6} {:<8} {:>9} {:>10} {:>6} {:>8} {:>7} {:>6} {:>6} {:>6}",
+        "id", "kind", "codec", "seconds", "bytes", "bits", "cycles", "frame%", "LUFS", "dBTP", "gain"
+    );
+    for s in sounds {
+        println!(
+            "{:<24} {:<6} {:<8} {:>9.2} {:>10} {:>6.2} {:>8} {:>6.1}% {:>6} {:>6.1} {:>+6.1}",
+            s.id,
+            s.kind,
+            s.codec,
+            s.duration_seconds,
+            s.encoded_bytes,
+            s.bits_per_sample,
+            s.max_decode_cycles,
+            s.max_decode_percent,
+            s.integrated_lufs.map_or("-inf".to_string(), |x| format!("{:.1}", x)),
+            s.true_peak_dbtp,
+            s.applied_gain_db
+        );
+    }
+    println!(
+        "audio total: {} bytes ({:.1}% of ROM{})",
+        total_bytes,
+        100.0 * total_bytes as f64 / ROM_SIZE as f64,
+        project
+            .audio_rom_budget_bytes
+            .map_or(String::new(), |b| format!(", {:.1}% of budget", 100.0 * total_bytes as f64 / b as f64))
+    );
+
+    let path = report_path();
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(
+        &path,
+        toml::to_string(&Report {
+            total_bytes,
+            rom_size: ROM_SIZE,
+            audio_rom_budget_bytes: project.audio_rom_budget_bytes,
+            max_decode_percent: project.max_decode_percent,
+            sounds,
+        })?,
+    )?;
+
+    let mut problems = Vec::new();
+    if let Some(budget) = project.audio_rom_budget_bytes {
+        if total_bytes > budget {
+            problems.push(format!(
+                "audio takes {} bytes, over the budget of {} by {}",
+                total_bytes,
+                budget,
+                total_bytes - budget
+            ));
+        }
+    }
+    if let Some(max_percent) = project.max_decode_percent {
+        for s in sounds.iter().filter(|s| s.max_decode_percent > max_percent) {
+            problems.push(format!(
+                "{}: worst frame predicted at {:.1}% of a frame to decode, over the limit of {:.1}%",
+                s.id, s.max_decode_percent, max_percent
+            ));
+        }
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("sound budget exceeded (see {}):\n{}", path.to_string_lossy(), problems.join("\n")).into())
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright (C) 2021 lifning, licensed under the GNU Affero General Public License version 3.
+
+use std::error::Error;
+use std::path::Path;
+
+pub struct WavMono16 {
+    pub sample_rate: u32,
+    pub samples: Vec<i16>,
+}
+
+fn read_u16_le(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32_le(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+/// just enough RIFF parsing for what ffmpeg hands us: 16-bit PCM, one channel.
+pub fn read_wav_mono16(path: impl AsRef<Path>) -> Result<WavMono16, Box<dyn Error>> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path)?;
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(format!("{}: not a RIFF WAVE file", path.to_string_lossy()).into());
+    }
+
+    let mut sample_rate = None;
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let chunk_id = &bytes[offset..offset + 4];
+        let chunk_len = read_u32_le(&bytes, offset + 4) as usize;
+        let body_start = offset + 8;
+        let body_end = (body_start + chunk_len).min(bytes.len());
+        let body = &bytes[body_start..body_end];
+        match chunk_id {
+            b"fmt " => {
+                if body.len() < 16 {
+                    return Err("fmt chunk too short".into());
+                }
+                let format = read_u16_le(body, 0);
+                let channels = read_u16_le(body, 2);
+                let bits_per_sample = read_u16_le(body, 14);
+                // 0xFFFE is WAVE_FORMAT_EXTENSIBLE, which some encoders like to emit
+                if (format != 1 && format != 0xFFFE) || channels != 1 || bits_per_sample != 16 {
+                    return Err(format!(
+                        "{}: expected mono 16-bit PCM, got format {:#x}, {} channels, {} bits",
+                        path.to_string_lossy(),
+                        format,
+                        channels,
+                        bits_per_sample
+                    )
+                    .into());
+                }
+                sample_rate = Some(read_u32_le(body, 4));
+            }
+            b"data" => {
+                let sample_rate = sample_rate.ok_or("data chunk before fmt chunk")?;
+                let samples = body
+                    .chunks_exact(2)
+                    .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+                    .collect();
+                return Ok(WavMono16 {
+                    sample_rate,
+                    samples,
+                });
+            }
+            _ => {}
+        }
+        // chunks are padded to even lengths
+        offset = body_start + chunk_len + (chunk_len & 1);
+    }
+
+    Err(format!("{}: no data chunk", path.to_string_lossy()).into())
+}
+
+pub fn write_wav_mono16(
+    path: impl AsRef<Path>,
+    sample_rate: u32,
+    samples: &[i16],
+) -> Result<(), Box<dyn Error>> {
+    let data_len = samples.len() as u32 * 2;
+    let mut bytes = Vec::with_capacity(44 + data_len as usize);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    bytes.extend_from_slice(&1u16.to_le_bytes()); // mono
+    bytes.extend_from_slice(&sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&(sample_rate * 2).to_le_bytes()); // bytes per second
+    bytes.extend_from_slice(&2u16.to_le_bytes()); // block align
+    bytes.extend_from_slice(&16u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        bytes.extend_from_slice(&sample.to_le_bytes());
+    }
+    std::fs::write(path, bytes)?;
+    Ok(())
+}
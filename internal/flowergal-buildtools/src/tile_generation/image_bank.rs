@@ -8,7 +8,15 @@ use sdl2::surface::Surface;
 use flowergal_proj_config::resources::{PaletteData, TilePatterns};
 
 use crate::tile_generation::tile_pixels::TilePixelsBank;
-use crate::tile_generation::{sdl_support, traverse_16_grid_as_8, Grid, PaletteBank, Pattern, PatternBank, RoomBank, SbEntry, PAL_LEN, TILE_H, TILE_W, PalBankId};
+use crate::tile_generation::{sdl_support, traverse_16_grid_as_8, Grid, MapLayout, PaletteBank, Pattern, PatternBank, RoomBank, SbEntry, PAL_LEN, TILE_H, TILE_W, PalBankId};
+
+/// one row of an animated tile sheet.  the first column is the tile as it appears in the
+/// maps, and the rest (up to the first fully transparent one) are the frames that follow it.
+pub struct AnimTileFrames {
+    pub tile_num: usize,
+    /// as they need to be stored in the charblock, starting with the one already there
+    pub frames: Vec<Pattern>,
+}
 
 pub struct ImageBank {
     // charblock 0
@@ -18,22 +26,47 @@ pub struct ImageBank {
     pub palette_bank: PaletteBank,
     pub tile_pixels_bank: TilePixelsBank,
     pub tile_pixels_bank_special: TilePixelsBank,
+    pub main_is_4bpp: bool,
     pub special_is_4bpp: bool,
 }
 
 impl ImageBank {
-    pub fn new(max_colors: usize, special_is_4bpp: bool) -> Self {
+    /// 8bpp main tiles are text-mode and get the whole palette to themselves (so any special
+    /// ones have to be 8bpp too); 8bpp special tiles are for an affine skybox.
+    pub fn new(max_colors: usize, main_is_4bpp: bool, special_is_4bpp: bool) -> Self {
         assert!(max_colors <= 256);
+        let palette_bank = if main_is_4bpp {
+            PaletteBank::new(max_colors)
+        } else {
+            PaletteBank::new_flat(max_colors)
+        };
         ImageBank {
-            pattern_bank: PatternBank::new(true),
-            pattern_bank_special: PatternBank::new(special_is_4bpp),
-            palette_bank: PaletteBank::new(max_colors),
+            pattern_bank: PatternBank::new(main_is_4bpp, true),
+            pattern_bank_special: PatternBank::new(special_is_4bpp, special_is_4bpp),
+            palette_bank,
             tile_pixels_bank: TilePixelsBank::new(),
             tile_pixels_bank_special: TilePixelsBank::new(),
+            main_is_4bpp,
             special_is_4bpp,
         }
     }
 
+    fn is_4bpp(&self, special: bool) -> bool {
+        if special {
+            self.special_is_4bpp
+        } else {
+            self.main_is_4bpp
+        }
+    }
+
+    fn layout(&self, special: bool) -> MapLayout {
+        match (self.is_4bpp(special), special) {
+            (true, _) => MapLayout::Text4bpp,
+            (false, false) => MapLayout::Text8bpp,
+            (false, true) => MapLayout::Affine,
+        }
+    }
+
     pub fn process_world_map(
         &mut self,
         surf: &Surface,
@@ -41,7 +74,7 @@ impl ImageBank {
         tile8_is_blended: impl Fn(usize, usize) -> bool,
         (room_width, room_height): (usize, usize)
     ) -> Result<RoomBank, Box<dyn Error>> {
-        let is_4bpp = !special || self.special_is_4bpp;
+        let is_4bpp = self.is_4bpp(special);
 
         // optimize palette color packing (feeding them to pattern/palette banks in the right order)
         if is_4bpp {
@@ -77,7 +110,7 @@ impl ImageBank {
             }
         }
 
-        let mut metamap = RoomBank::new(surf.size(), (room_width, room_height), is_4bpp);
+        let mut metamap = RoomBank::new(surf.size(), (room_width, room_height), self.layout(special));
         for ry in 0..(metamap.map_height / room_height) {
             for rx in 0..(metamap.map_width / room_width) {
                 for (x, y) in traverse_16_grid_as_8(room_width, room_height) {
@@ -114,8 +147,7 @@ impl ImageBank {
         );
         let ofsx = rect.x() as usize / TILE_W;
         let ofsy = rect.y() as usize / TILE_H;
-        let is_4bpp = !special || self.special_is_4bpp;
-        let mut grid = Grid::new(grid_size, is_4bpp);
+        let mut grid = Grid::new(grid_size, self.layout(special) != MapLayout::Affine);
 
         for ty in 0..grid.grid_height {
             for tx in 0..grid.grid_width {
@@ -133,6 +165,97 @@ impl ImageBank {
         Ok(grid)
     }
 
+    /// `maps` are the already-processed rooms, which find their animated tiles by having the
+    /// same pixels as the first column.  anything else that deduplicated onto the same tile
+    /// (flipped, or in another palbank) would animate too, so that fails the build.
+    pub fn process_anim_tiles(
+        &mut self,
+        surf: &Surface,
+        blend: bool,
+        maps: &[RoomBank],
+    ) -> Result<Vec<AnimTileFrames>, Box<dyn Error>> {
+        let grid_width = surf.width() as usize / TILE_W;
+        let grid_height = surf.height() as usize / TILE_H;
+        let mut anims: Vec<AnimTileFrames> = Vec::with_capacity(grid_height);
+
+        for ty in 0..grid_height {
+            let base_colors = sdl_support::pixels_of_tile(&surf, 0, ty)?;
+            let base = self
+                .try_onboard_tile_colors(&base_colors, true, false, blend)
+                .ok_or_else(|| self.error(0, ty).unwrap_err())?;
+            if anims.iter().any(|a| a.tile_num == base.tile_num) {
+                return Err(format!(
+                    "animated tile row {} starts with the same tile as an earlier row",
+                    ty
+                )
+                .into());
+            }
+            if let Some((mx, my)) = Self::find_other_use(maps, &base) {
+                return Err(format!(
+                    "animated tile row {} has the same pixels as a static tile at ({}, {}) once \
+                    that's flipped or recolored, so it would animate too",
+                    ty,
+                    mx * TILE_W,
+                    my * TILE_H
+                )
+                .into());
+            }
+
+            let mut frames = vec![Pattern(self.pattern_bank.patterns[base.tile_num].0.clone())];
+            for tx in 1..grid_width {
+                let pixel_colors = sdl_support::pixels_of_tile(&surf, tx, ty)?;
+                if pixel_colors.iter().all(|c| c.0 & (1 << 15) == 0) {
+                    break;
+                }
+                // every frame has to work with the palbank the maps already use for this tile
+                let mut pattern = self
+                    .palette_bank
+                    .palette_mut(base.palbank)
+                    .try_onboard_tile(&pixel_colors)
+                    .ok_or_else(|| {
+                        format!(
+                            "animated tile frame at ({}, {}) has colors that don't fit its first frame's palette",
+                            tx * TILE_W,
+                            ty * TILE_H
+                        )
+                    })?;
+                // the maps flip the stored tile to get the first frame; make the rest match
+                if base.hflip {
+                    pattern = pattern.hflip();
+                }
+                if base.vflip {
+                    pattern = pattern.vflip();
+                }
+                frames.push(pattern);
+            }
+
+            anims.push(AnimTileFrames {
+                tile_num: base.tile_num,
+                frames,
+            });
+        }
+
+        Ok(anims)
+    }
+
+    /// where a map uses `sbe`'s tile with a different flip or palbank, if anywhere
+    fn find_other_use(maps: &[RoomBank], sbe: &SbEntry) -> Option<(usize, usize)> {
+        for map in maps {
+            for (ry, row) in map.rooms.iter().enumerate() {
+                for (rx, grid) in row.iter().enumerate() {
+                    for (y, entries) in grid.sb_entries.iter().enumerate() {
+                        for (x, entry) in entries.iter().enumerate() {
+                            if entry.tile_num == sbe.tile_num && entry != sbe {
+                                return Some((rx * map.room_width + x, ry * map.room_height + y));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+
     pub fn try_onboard_tile_colors(
         &mut self,
         pixel_colors: &[gba::Color],
@@ -142,24 +265,24 @@ impl ImageBank {
     ) -> Option<SbEntry> {
         if let Some((mut pattern, mut palbank)) = self.palette_bank.try_onboard_colors(pixel_colors, blend)
         {
-            let pattern_bank;
-            if special {
-                if !self.special_is_4bpp {
-                    // FIXME: how to *actually* handle this weird case with PalBankId?
-                    //  for now assuming that it's blended; i think the only 8bpp specials are.
-                    let palbank_index = if let PalBankId::Blended(x) = palbank {
-                        x
-                    } else {
-                        unimplemented!("non-blended 8bpp tiles??")
-                    };
-                    for x in pattern.0.iter_mut() {
-                        *x += palbank_index * PAL_LEN;
-                    }
-                    palbank = PalBankId::Blended(0);
+            if !self.is_4bpp(special) {
+                // FIXME: how to *actually* handle this weird case with PalBankId?
+                //  for now assuming that it's blended; i think the only 8bpp specials are.
+                //  (and in a flat palette, everything is)
+                let palbank_index = if let PalBankId::Blended(x) = palbank {
+                    x
+                } else {
+                    unimplemented!("non-blended 8bpp tiles??")
+                };
+                for x in pattern.0.iter_mut() {
+                    *x += palbank_index * PAL_LEN;
                 }
-                pattern_bank = &mut self.pattern_bank_special;
+                palbank = PalBankId::Blended(0);
+            }
+            let pattern_bank = if special {
+                &mut self.pattern_bank_special
             } else {
-                pattern_bank = &mut self.pattern_bank;
+                &mut self.pattern_bank
             };
 
             let option_sb_entry = if deduplicate {
@@ -189,8 +312,10 @@ impl ImageBank {
             } else {
                 TilePatterns::Affine(Self::collect_patterns(&self.pattern_bank_special))
             }
-        } else {
+        } else if self.main_is_4bpp {
             TilePatterns::Text(Self::collect_patterns(&self.pattern_bank))
+        } else {
+            TilePatterns::Affine(Self::collect_patterns(&self.pattern_bank))
         }
     }
 
@@ -221,9 +346,9 @@ impl ImageBank {
         if self.palette_bank.palettes_blend.is_empty() {
             0
         } else {
-            let padded = self.palette_bank.palettes_blend.len() * 16;
+            // every palette but the last is padded out to 16 (a flat one is just the one)
             let last_len = self.palette_bank.palettes_blend.last().unwrap().colors.len();
-            padded - (16 - last_len)
+            (self.palette_bank.palettes_blend.len() - 1) * PAL_LEN + last_len
         }
     }
 
@@ -120,22 +120,33 @@ impl Grid {
     }
 }
 
+/// what kind of screenblock entries a `RoomBank` turns into
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum MapLayout {
+    Text4bpp,
+    /// text screenblock entries pointing at 8bpp tiles
+    Text8bpp,
+    Affine,
+}
+
 pub struct RoomBank {
     pub rooms: Vec<Vec<Grid>>,
     pub room_width: usize,
     pub room_height: usize,
     pub map_width: usize,
     pub map_height: usize,
-    pub is_4bpp: bool,
+    pub layout: MapLayout,
 }
 
 impl RoomBank {
-    pub fn new(map_size_pixels: (u32, u32), room_size: (usize, usize), is_4bpp: bool) -> Self {
+    pub fn new(map_size_pixels: (u32, u32), room_size: (usize, usize), layout: MapLayout) -> Self {
         let (room_width, room_height) = room_size;
         let map_width = map_size_pixels.0 as usize / TILE_W;
         let map_height = map_size_pixels.1 as usize / TILE_H;
+        // (the grids only care about which kind of screenblock entry)
+        let is_text = layout != MapLayout::Affine;
         let rooms = vec![
-            vec![Grid::new(room_size, is_4bpp); map_width / room_width];
+            vec![Grid::new(room_size, is_text); map_width / room_width];
             map_height / room_height
         ];
         RoomBank {
@@ -144,7 +155,7 @@ impl RoomBank {
             room_height,
             map_width,
             map_height,
-            is_4bpp,
+            layout,
         }
     }
 
@@ -164,7 +175,7 @@ impl RoomBank {
         for row in &self.rooms {
             let mut meta_inner = Vec::with_capacity(row.len());
             for room in row {
-                if self.is_4bpp {
+                if self.layout != MapLayout::Affine {
                     meta_inner.push(room_data_4bpp.len() as u8);
                     room_data_4bpp.push(room.gba_room_entries_4bpp(pal_bank_ofs));
                 } else {
@@ -175,10 +186,10 @@ impl RoomBank {
             meta_outer.push(Box::leak(meta_inner.into_boxed_slice()));
         }
         let mm = Metamap(Box::leak(meta_outer.into_boxed_slice()));
-        let rd = if self.is_4bpp {
-            RoomData::Text(Box::leak(room_data_4bpp.into_boxed_slice()))
-        } else {
-            RoomData::Affine(Box::leak(room_data_8bpp.into_boxed_slice()))
+        let rd = match self.layout {
+            MapLayout::Text4bpp => RoomData::Text(Box::leak(room_data_4bpp.into_boxed_slice())),
+            MapLayout::Text8bpp => RoomData::Text8bpp(Box::leak(room_data_4bpp.into_boxed_slice())),
+            MapLayout::Affine => RoomData::Affine(Box::leak(room_data_8bpp.into_boxed_slice())),
         };
         (mm, rd)
     }
@@ -62,7 +62,7 @@ pub fn process_basic_tilesets<'a>(
     surfaces: impl IntoIterator<Item = Surface<'a>>,
     max_colors: usize,
 ) -> Result<(Vec<Grid>, ImageBank), Box<dyn Error>> {
-    let mut bank = ImageBank::new(max_colors, true);
+    let mut bank = ImageBank::new(max_colors, true, true);
 
     let mut grids = Vec::new();
 
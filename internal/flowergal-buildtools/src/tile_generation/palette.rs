@@ -104,7 +104,23 @@ impl PaletteBank {
         }
     }
 
+    /// one big palette for 8bpp tiles to pick from, instead of 16-color palbanks.
+    /// everything in it gets blended.
+    pub fn new_flat(max_colors: usize) -> Self {
+        PaletteBank {
+            palettes_blend: Vec::new(),
+            palettes_plain: Vec::new(),
+            max_palettes: 1,
+            palette_max_colors: max_colors,
+        }
+    }
+
+    pub fn is_flat(&self) -> bool {
+        self.palette_max_colors > PAL_LEN
+    }
+
     pub fn try_onboard_colors(&mut self, pixel_colors: &[gba::Color], blend: bool) -> Option<(Pattern, PalBankId)> {
+        let blend = blend || self.is_flat();
         /*
         if let Some(x) = self.find_existing_colors(pixel_colors, blend) {
             return Some(x)
@@ -128,6 +144,13 @@ impl PaletteBank {
         None
     }
 
+    pub fn palette_mut(&mut self, palbank: PalBankId) -> &mut Palette {
+        match palbank {
+            PalBankId::Plain(x) => &mut self.palettes_plain[x],
+            PalBankId::Blended(x) => &mut self.palettes_blend[x],
+        }
+    }
+
     /*
     fn find_existing_colors(&self, pixel_colors: &[gba::Color], blend: bool) -> Option<(Pattern, PalBankId)> {
         let pal_set = if blend { &self.palettes_blend } else { &self.palettes_plain };
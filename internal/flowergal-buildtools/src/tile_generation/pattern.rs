@@ -66,14 +66,18 @@ pub struct PatternBank {
     pub patterns: Vec<Pattern>,
     pub max_patterns: usize,
     pub is_4bpp: bool,
+    /// text-mode screenblock entries can flip tiles, affine ones can't
+    pub flippable: bool,
 }
 impl PatternBank {
-    pub fn new(is_4bpp: bool) -> Self {
+    pub fn new(is_4bpp: bool, flippable: bool) -> Self {
+        // either way, that's one charblock's worth
         let max_patterns = if is_4bpp { 512 } else { 256 };
         PatternBank {
             patterns: vec![Pattern(vec![0; TILE_W * TILE_H])],
             max_patterns,
             is_4bpp,
+            flippable,
         }
     }
 
@@ -86,7 +90,7 @@ impl PatternBank {
                 palbank,
             });
         }
-        if self.is_4bpp {
+        if self.flippable {
             let h_flipped = new_pattern.hflip();
             if let Some(tile_num) = self.patterns.iter().position(|img| *img == h_flipped) {
                 return Some(SbEntry {
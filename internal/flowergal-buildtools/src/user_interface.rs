@@ -9,10 +9,74 @@ use sdl2::surface::Surface;
 use build_const::ConstWriter;
 
 use crate::tile_generation;
+use crate::tile_generation::sdl_support::pixels_of_tile;
 use flowergal_proj_config::resources::TilePatterns;
 
 const ASSET_DIR: &str = "../../assets/gfx";
 const ASSETS: &[&str] = &["hud.png", "font.png", "textbox.png"];
+const FONT_ASSET_INDEX: usize = 1;
+
+/// font.png is ASCII from ' ' to DEL, in 8x8 cells
+const FONT_FIRST_CHAR: usize = 0x20;
+const FONT_GLYPHS: usize = 96;
+const SPACE_ADVANCE: u8 = 4;
+/// between the rightmost (shadow) pixel of one glyph and the next
+const LETTER_SPACING: u8 = 1;
+
+/// 2bpp rows (pixel x in bits 2x and 2x+1; 0 transparent, 1 ink, 2 shadow) and advance widths
+struct Font {
+    glyphs: Vec<[u16; 8]>,
+    advances: Vec<u8>,
+    /// the two opaque colors, brighter first
+    colors: [gba::Color; 2],
+}
+
+fn brightness(c: gba::Color) -> u16 {
+    (c.0 & 0x1F) + ((c.0 >> 5) & 0x1F) + ((c.0 >> 10) & 0x1F)
+}
+
+fn read_font(surf: &Surface) -> Result<Font, Box<dyn Error>> {
+    let cols = surf.width() as usize / 8;
+    let mut tiles = Vec::with_capacity(FONT_GLYPHS);
+    let mut colors = Vec::new();
+    for index in 0..FONT_GLYPHS {
+        let pixels = pixels_of_tile(surf, index % cols, index / cols)?;
+        for c in pixels.iter().filter(|c| c.0 & 0x8000 != 0) {
+            if !colors.contains(&gba::Color(c.0 & 0x7FFF)) {
+                colors.push(gba::Color(c.0 & 0x7FFF));
+            }
+        }
+        tiles.push(pixels);
+    }
+    if colors.len() != 2 {
+        return Err(format!("font.png should have exactly 2 opaque colors (ink and shadow), found {}", colors.len()).into());
+    }
+    colors.sort_by_key(|c| std::cmp::Reverse(brightness(*c)));
+
+    let mut glyphs = Vec::with_capacity(FONT_GLYPHS);
+    let mut advances = Vec::with_capacity(FONT_GLYPHS);
+    for pixels in tiles {
+        let mut glyph = [0u16; 8];
+        let mut rightmost = None;
+        for (i, c) in pixels.iter().enumerate() {
+            if c.0 & 0x8000 != 0 {
+                let code = if c.0 & 0x7FFF == colors[0].0 { 1 } else { 2 };
+                glyph[i / 8] |= code << ((i % 8) * 2);
+                rightmost = rightmost.max(Some(i % 8));
+            }
+        }
+        glyphs.push(glyph);
+        advances.push(match rightmost {
+            Some(x) => (x as u8 + 1 + LETTER_SPACING).min(8),
+            None => SPACE_ADVANCE,
+        });
+    }
+    Ok(Font {
+        glyphs,
+        advances,
+        colors: [colors[0], colors[1]],
+    })
+}
 
 pub fn convert_assets() -> Result<(), Box<dyn Error>> {
     let _sdl_context = sdl2::init()?;
@@ -27,6 +91,8 @@ pub fn convert_assets() -> Result<(), Box<dyn Error>> {
         surfs.push(Surface::from_file(&surf_path)?);
     }
 
+    let font = read_font(&surfs[FONT_ASSET_INDEX])?;
+
     // TODO: eventually keep track of multi-palette / flip reduction etc.? not necessary yet
     let (_grids, bank) = tile_generation::process_basic_tilesets(surfs, 16)?;
 
@@ -34,6 +100,22 @@ pub fn convert_assets() -> Result<(), Box<dyn Error>> {
         let pal = bank.gba_palette_full();
         bc_out.add_array("UI_PAL", "Color", &pal.data());
         bc_out.add_array("UI_IMG", "Tile4bpp", img);
+
+        // which HUD palette entries the 2bpp glyph codes expand to
+        let mut font_colors = [0u8; 4];
+        for (code, color) in font.colors.iter().enumerate() {
+            let index = pal
+                .data()
+                .iter()
+                .skip(1)
+                .position(|c| c.0 & 0x7FFF == color.0)
+                .ok_or("font color missing from UI palette")?;
+            font_colors[code + 1] = index as u8 + 1;
+        }
+        bc_out.add_value("VWF_FIRST_CHAR", "usize", FONT_FIRST_CHAR);
+        bc_out.add_array("VWF_GLYPHS", "[u16; 8]", &font.glyphs);
+        bc_out.add_array("VWF_ADVANCES", "u8", &font.advances);
+        bc_out.add_value("VWF_COLORS", "[u8; 4]", font_colors);
     } else {
         return Err("Found UI assets in 8bpp format, unsupported".into());
     }
@@ -12,10 +12,10 @@ use sdl2::pixels::PixelFormatEnum;
 use sdl2::surface::Surface;
 
 use crate::compression::CompressibleAsset;
-use crate::tile_generation::{sdl_support, ImageBank, Grid};
+use crate::tile_generation::{sdl_support, ImageBank, RoomBank};
 use flowergal_proj_config::resources::blend::float;
-use flowergal_proj_config::resources::{ColorEffectType, Layer, PaletteData, WorldData, WorldPalettes, BLEND_ENTRIES, TEXTBOX_A, TEXTBOX_B, TEXTBOX_G, TEXTBOX_R, TEXTBOX_Y_MID_EFFECT_INDEX, ROOM_SIZE};
-use flowergal_proj_config::{WorldId, WorldResourceInfo, WORLD_RESOURCE_INFO};
+use flowergal_proj_config::resources::{AnimTile, AnimTiles, ColorEffectType, Tile4bpp, KeyframeColors, Layer, PaletteCycle, PaletteCycleKind, PaletteCycleSource, PaletteData, WorldData, WorldPalettes, BLEND_ENTRIES, TEXTBOX_A, TEXTBOX_B, TEXTBOX_G, TEXTBOX_R, TEXTBOX_Y_MID_EFFECT_INDEX, ROOM_SIZE};
+use flowergal_proj_config::{WorldResourceInfo, WORLD_RESOURCE_INFO};
 
 const ASSET_DIR: &str = "../../assets/gfx";
 const TILEMAPS_DIR: &str = "../../assets/tilemaps";
@@ -107,16 +107,31 @@ fn generate_blend_palettes(
 fn generate_animtiles(
     world: &WorldResourceInfo,
     bank: &mut ImageBank,
-) -> Result<Grid, Box<dyn Error>> {
+    rooms: &[RoomBank],
+) -> Result<Vec<AnimTile>, Box<dyn Error>> {
     if let Some(anim_path) = world.anim_path {
+        if world.main_8bpp {
+            return Err("animated tiles aren't supported with main_8bpp yet".into());
+        }
         let surf = load_surface_resource(ANIMTILES_DIR, anim_path)?;
         let blend = match world.id {
             _ => true,
         };
-        let grid = bank.process_image_region(&surf, None, true, false, blend)?;
-        Ok(grid)
+        let anims = bank.process_anim_tiles(&surf, blend, rooms)?;
+        let default_timing = *world.anim_timing.last().ok_or("anim_path given without anim_timing")?;
+        let anim_tiles = anims
+            .into_iter()
+            .enumerate()
+            .filter(|(_, anim)| anim.frames.len() > 1)
+            .map(|(row, anim)| AnimTile {
+                tile_num: anim.tile_num as u16,
+                frames_per_step: world.anim_timing.get(row).copied().unwrap_or(default_timing).max(1),
+                frames: Box::leak(anim.frames.iter().map(|p| p.into()).collect::<Vec<Tile4bpp>>().into_boxed_slice()),
+            })
+            .collect();
+        Ok(anim_tiles)
     } else {
-        Ok(Grid::new((0, 0), true))
+        Ok(Vec::new())
     }
 }
 
@@ -163,23 +178,112 @@ fn compute_blend_effects(
     let base_pal = PaletteData::new(base_pal.leak());
     let textbox_blend_palette = compute_textbox_palette(&base_pal);
 
+    let cycles = world
+        .palette_cycles
+        .iter()
+        .map(|cycle| blend_palette_cycle(world, cycle, normal_palette.data(), blend_len))
+        .collect::<Result<Vec<_>, _>>()?;
+
     Ok(WorldPalettes {
         normal_palette,
         blended_palettes,
         textbox_blend_palette,
+        cycles: Box::leak(cycles.into_boxed_slice()),
         // gradient_colors: PaletteData(Box::leak(sdl_support::sdl_to_gba_colors(blend_colors).into_boxed_slice())),
     })
 }
 
 fn compute_textbox_palette(base_pal: &PaletteData) -> PaletteData {
+    let text_pal = blend_textbox_colors(base_pal.data());
+    PaletteData::new(Box::leak(text_pal.into_boxed_slice()))
+}
+
+fn blend_textbox_colors(colors: &[gba::Color]) -> Vec<gba::Color> {
     let textbox_rgb = (TEXTBOX_R as u8, TEXTBOX_G as u8, TEXTBOX_B as u8);
     let alpha = TEXTBOX_A as f64 / 255.0;
-    let text_pal: Vec<gba::Color> = base_pal
-        .data()
+    colors
         .iter()
         .map(|a| float::blend_alpha(*a, textbox_rgb, alpha))
-        .collect();
-    PaletteData::new(Box::leak(text_pal.into_boxed_slice()))
+        .collect()
+}
+
+fn palette_cycle_colors(cycle: &PaletteCycleSource) -> Vec<gba::Color> {
+    sdl_support::sdl_to_gba_colors(cycle.colors.iter().map(|(r, g, b)| sdl2::pixels::Color::RGB(*r, *g, *b)))
+}
+
+/// before any maps get processed, so each cycle's colors are packed together, in order
+fn reserve_palette_cycles(world: &WorldResourceInfo, bank: &mut ImageBank) -> Result<(), Box<dyn Error>> {
+    for cycle in world.palette_cycles {
+        if cycle.colors.is_empty() || cycle.frames_per_step == 0 {
+            return Err(format!(
+                "palette cycle {:?} needs some colors and a nonzero frames_per_step",
+                cycle.colors
+            )
+            .into());
+        }
+        bank.palette_bank
+            .try_onboard_colors(&palette_cycle_colors(cycle), true)
+            .ok_or_else(|| format!("palette cycle {:?} doesn't fit in a palbank", cycle.colors))?;
+    }
+    Ok(())
+}
+
+/// finds where the cycle's colors ended up in the palette, and for keyframes, precomputes
+/// how the colors look under the same effects as the rest of the palette.
+fn blend_palette_cycle(
+    world: &WorldResourceInfo,
+    cycle: &PaletteCycleSource,
+    palette: &[gba::Color],
+    blend_len: usize,
+) -> Result<PaletteCycle, Box<dyn Error>> {
+    let colors = palette_cycle_colors(cycle);
+    let len = colors.len();
+    let start = palette.windows(len).position(|run| run == &colors[..]).ok_or_else(|| {
+        format!(
+            "palette cycle {:?} didn't end up as one run in the palette; is a color repeated, \
+            or the same as the backdrop?",
+            cycle.colors
+        )
+    })?;
+
+    let kind = match &cycle.kind {
+        PaletteCycleKind::Keyframes(keyframes) => {
+            let normal = keyframes.normal;
+            if normal.is_empty() || normal.len() % len != 0 {
+                return Err(format!(
+                    "palette cycle at {}: {} keyframe colors isn't a multiple of its len {}",
+                    start,
+                    normal.len(),
+                    len
+                )
+                .into());
+            }
+            // colors past blend_len don't get the gradient, so neither should these
+            let blended: Vec<&'static [gba::Color]> = if start + len <= blend_len {
+                compute_overlay_palettes(world, normal)?
+                    .iter()
+                    .map(|pal| &pal.data()[..normal.len()])
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            let textbox_base = blended.get(TEXTBOX_Y_MID_EFFECT_INDEX).copied().unwrap_or(normal);
+            let textbox = blend_textbox_colors(textbox_base);
+            PaletteCycleKind::Keyframes(KeyframeColors {
+                normal,
+                blended: Box::leak(blended.into_boxed_slice()),
+                textbox: Box::leak(textbox.into_boxed_slice()),
+            })
+        }
+        kind => kind.clone(),
+    };
+
+    Ok(PaletteCycle {
+        start: start as u8,
+        len: len as u8,
+        frames_per_step: cycle.frames_per_step,
+        kind,
+    })
 }
 
 fn compute_overlay_palettes(
@@ -218,12 +322,18 @@ fn compute_overlay_palettes(
 }
 
 fn generate_world_data(world: &WorldResourceInfo) -> Result<WorldData, Box<dyn Error>> {
-    let special_is_4bpp = world.id != WorldId::TomsDiner;
+    // an affine skybox has to be 8bpp
+    let special_is_4bpp = world.skybox_anim.affine.is_none();
     let max_colors = 240;
+    if world.main_8bpp && special_is_4bpp && world.skybox_path.is_some() {
+        return Err("main_8bpp needs an affine skybox, its palette has no room for palbanks".into());
+    }
 
-    let mut bank = ImageBank::new(max_colors, special_is_4bpp);
+    let mut bank = ImageBank::new(max_colors, !world.main_8bpp, special_is_4bpp);
     let mut rooms = Vec::new();
 
+    reserve_palette_cycles(world, &mut bank)?;
+
     // render actual level maps
     let map_surfs = render_world_to_16bit_surfaces(world)?;
     for surf in map_surfs.into_iter() {
@@ -231,8 +341,7 @@ fn generate_world_data(world: &WorldResourceInfo) -> Result<WorldData, Box<dyn E
         rooms.push(bank.process_world_map(&surf, false, tile8_is_blended, ROOM_SIZE)?);
     }
     // animated tiles... might get crowded
-    // TODO: save grid's.. grid
-    let _anim = generate_animtiles(world, &mut bank)?;
+    let anim_tiles = generate_animtiles(world, &mut bank, &rooms)?;
 
     // FIXME: subtle: starts converting SbEntries on its own, needs refactor
     let skybox_layer = generate_skybox(world, &mut bank)?;
@@ -264,7 +373,8 @@ fn generate_world_data(world: &WorldResourceInfo) -> Result<WorldData, Box<dyn E
         bg_layer,
         fg_layer,
         skybox_layer,
-        // TODO anim: Box::leak(anim.into_boxed_slice()), anim_grid
+        anim_tiles: AnimTiles(Box::leak(anim_tiles.into_boxed_slice())),
+        skybox_anim: world.skybox_anim,
         music: world.songs.clone(),
     };
 
@@ -0,0 +1,71 @@
+use std::error::Error;
+
+use build_const::ConstWriter;
+
+// the full manifest is parsed by flowergal-buildtools; we only need the ids out of it here,
+// and buildtools depends on us, so we can't borrow its parser.
+const SOUND_MANIFEST: &str = "../../assets/sound_manifest.toml";
+
+/// (id, title) for each entry, where the title defaults to the id
+fn sound_ids(manifest: &toml::Value, section: &str) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let entries = match manifest.get(section) {
+        Some(toml::Value::Array(entries)) => entries.as_slice(),
+        Some(_) => return Err(format!("[[{}]] must be an array of tables", section).into()),
+        None => &[],
+    };
+    let mut ids = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let id = entry
+            .get("id")
+            .and_then(toml::Value::as_str)
+            .ok_or_else(|| format!("every [[{}]] needs a string id", section))?;
+        let valid = id.chars().next().map_or(false, |c| c.is_ascii_alphabetic())
+            && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(format!("{} id {:?} isn't a valid enum variant name", section, id).into());
+        }
+        if ids.iter().any(|(x, _)| x == id) {
+            return Err(format!("duplicate {} id {:?}", section, id).into());
+        }
+        let title = match entry.get("title") {
+            Some(title) => title.as_str().ok_or_else(|| format!("{}: title must be a string", id))?,
+            None => id,
+        };
+        ids.push((id.to_string(), title.to_string()));
+    }
+    Ok(ids)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    println!("cargo:rerun-if-changed={}", SOUND_MANIFEST);
+    let manifest: toml::Value = std::fs::read_to_string(SOUND_MANIFEST)?.parse()?;
+
+    let mut bc_out = ConstWriter::for_build("sound_ids_bc")?.finish_dependencies();
+
+    bc_out.add_raw("/// music index in the DEBUG jukebox");
+    bc_out.add_raw("#[repr(usize)]");
+    bc_out.add_raw("#[cfg_attr(not(target_arch = \"arm\"), derive(Debug))]");
+    bc_out.add_raw("#[derive(Copy, Clone)]");
+    bc_out.add_raw("pub enum MusicId {");
+    let music = sound_ids(&manifest, "music")?;
+    for (id, _) in music.iter() {
+        bc_out.add_raw(&format!("    {},", id));
+    }
+    bc_out.add_raw("}");
+    let titles: Vec<String> = music.iter().map(|(_, title)| format!("{:?}", title)).collect();
+    bc_out.add_raw("/// display names for the jukebox, indexed by `MusicId`");
+    bc_out.add_raw(&format!("pub const MUSIC_TITLES: [&str; {}] = [{}];", titles.len(), titles.join(", ")));
+
+    bc_out.add_raw("/// sfx index in the jukebox");
+    bc_out.add_raw("#[allow(non_camel_case_types)]");
+    bc_out.add_raw("#[cfg_attr(not(target_arch = \"arm\"), derive(Debug))]");
+    bc_out.add_raw("#[derive(Copy, Clone)]");
+    bc_out.add_raw("pub enum SfxId {");
+    for (id, _) in sound_ids(&manifest, "sfx")? {
+        bc_out.add_raw(&format!("    {},", id));
+    }
+    bc_out.add_raw("}");
+
+    bc_out.finish();
+    Ok(())
+}
@@ -7,6 +7,9 @@
 #[macro_use]
 extern crate static_assertions;
 
+#[macro_use]
+extern crate build_const;
+
 pub mod resources;
 
 pub mod world_info;
@@ -43,6 +43,7 @@ pub struct RoomEntries8bpp(pub [[AffineScreenblockEntry; ROOM_SIZE.0/2]; ROOM_SI
 #[repr(transparent)]
 pub struct Metamap(pub &'static [&'static [u8]]);
 
+/// `Affine` & `AffineLz77` are 8bpp, which text-mode layers can use too (see `RoomData::Text8bpp`)
 pub enum TilePatterns {
     Text(&'static [Tile4bpp]),
     Affine(&'static [Tile8bpp]),
@@ -54,6 +55,18 @@ pub enum RoomData {
     Text(&'static [RoomEntries4bpp]),
     Affine(&'static [RoomEntries8bpp]),
     TextLz77(&'static [&'static [u32]]),
+    /// the same screenblock entries as `Text`, but for 8bpp tiles (so the palbanks go unused)
+    Text8bpp(&'static [RoomEntries4bpp]),
+    Text8bppLz77(&'static [&'static [u32]]),
+    /// for `lz77_uncomp_16bit`, since byte writes to VRAM land in both halves of the halfword
+    AffineLz77(&'static [&'static [u32]]),
+}
+
+impl RoomData {
+    /// whether the background needs to be set to 256 colors to show it
+    pub fn is_8bpp(&self) -> bool {
+        !matches!(self, RoomData::Text(_) | RoomData::TextLz77(_))
+    }
 }
 
 #[cfg_attr(not(target_arch = "arm"), derive(Debug))]
@@ -84,6 +97,57 @@ pub struct WorldPalettes {
     pub normal_palette: PaletteData,
     pub blended_palettes: &'static [PaletteData],
     pub textbox_blend_palette: PaletteData,
+    pub cycles: &'static [PaletteCycle],
+}
+
+/// animates palette indices `start..start + len`, moving along one step every
+/// `frames_per_step` frames.
+#[derive(Clone)]
+pub struct PaletteCycle {
+    pub start: u8,
+    pub len: u8,
+    pub frames_per_step: u8,
+    pub kind: PaletteCycleKind,
+}
+
+/// a `PaletteCycle` as a world's author writes it, by the colors in the source art.  the
+/// buildtools pack them next to each other in one palbank before the maps get a chance to
+/// scatter them, then look up where they ended up.
+#[derive(Clone)]
+pub struct PaletteCycleSource {
+    /// 24-bit RGB, in the order they cycle through
+    pub colors: &'static [(u8, u8, u8)],
+    pub frames_per_step: u8,
+    pub kind: PaletteCycleKind,
+}
+
+#[derive(Clone)]
+pub enum PaletteCycleKind {
+    /// every color moves up an index each step, and the last one wraps back around to the start
+    Rotate,
+    /// like `Rotate`, but turns around at either end instead of wrapping
+    PingPong,
+    /// swaps in the next `len` colors of a table each step
+    Keyframes(KeyframeColors),
+}
+
+#[derive(Clone)]
+pub struct KeyframeColors {
+    pub normal: &'static [Color],
+    /// `normal` after each of the world's `blended_palettes` effects. filled in at build time.
+    pub blended: &'static [&'static [Color]],
+    /// `normal` as seen through the textbox. also filled in at build time.
+    pub textbox: &'static [Color],
+}
+
+impl KeyframeColors {
+    pub const fn new(normal: &'static [Color]) -> Self {
+        KeyframeColors {
+            normal,
+            blended: &[],
+            textbox: &[],
+        }
+    }
 }
 
 pub enum ColorEffectType {
@@ -93,18 +157,122 @@ pub enum ColorEffectType {
     Multiply,
 }
 
+/// where playback picks back up after reaching the end of a looping sound.
+/// both are aligned to the codec's block size at build time.
+#[derive(Copy, Clone)]
+#[cfg_attr(not(target_arch = "arm"), derive(Debug))]
+pub struct LoopPoint {
+    pub sample: u32,
+    /// of the FLAC frame (or raw sample) starting at `sample`, from the start of the data
+    pub byte_offset: u32,
+}
+
+/// `None` for sounds that play once and stop.
 #[repr(align(4))]
 pub enum Sound {
-    RawPcm8(&'static [u8]),
-    Flac(&'static [u8]),
+    RawPcm8(&'static [u8], Option<LoopPoint>),
+    Flac(&'static [u8], Option<LoopPoint>),
 }
 
 impl Sound {
     pub fn data_ptr(&self) -> *const u8 {
         match self {
-            Sound::RawPcm8(x) | Sound::Flac(x) => x.as_ptr(),
+            Sound::RawPcm8(x, _) | Sound::Flac(x, _) => x.as_ptr(),
         }
     }
+
+    pub fn loop_point(&self) -> Option<LoopPoint> {
+        match self {
+            Sound::RawPcm8(_, lp) | Sound::Flac(_, lp) => *lp,
+        }
+    }
+}
+
+/// a tile in the world's main charblock that gets its graphics swapped out every so often
+pub struct AnimTile {
+    pub tile_num: u16,
+    pub frames_per_step: u8,
+    /// starting with the one that's there when the world's loaded
+    pub frames: &'static [Tile4bpp],
+}
+
+pub struct AnimTiles(pub &'static [AnimTile]);
+
+/// a value that changes from frame to frame
+#[derive(Copy, Clone)]
+pub enum Curve {
+    Constant(i32),
+    /// `start + per_frame * frame`
+    Linear { start: i32, per_frame: i32 },
+    /// up from 0 to `peak` and back down again, one step every `frames_per_step` frames,
+    /// starting from `peak`
+    Triangle { peak: i32, frames_per_step: u8 },
+    /// these values in a loop, one every `frames_per_step` frames
+    Table { values: &'static [i16], frames_per_step: u8 },
+}
+
+impl Curve {
+    pub fn at(&self, frame: i32) -> i32 {
+        match *self {
+            Curve::Constant(x) => x,
+            Curve::Linear { start, per_frame } => start.wrapping_add(per_frame.wrapping_mul(frame)),
+            Curve::Triangle { peak, frames_per_step } => {
+                let step = frame / frames_per_step.max(1) as i32;
+                (peak - step.rem_euclid(2 * peak.max(1))).abs()
+            }
+            Curve::Table { values, frames_per_step } => {
+                if values.is_empty() {
+                    return 0;
+                }
+                let step = frame / frames_per_step.max(1) as i32;
+                values[step.rem_euclid(values.len() as i32) as usize] as i32
+            }
+        }
+    }
+}
+
+/// the skybox as an affine background, spinning and/or zooming about a point
+#[derive(Copy, Clone)]
+#[cfg_attr(not(target_arch = "arm"), derive(Debug))]
+pub struct SkyboxAffine {
+    /// point in the skybox image, in pixels, that goes at `display_center`
+    pub data_center: (i32, i32),
+    pub display_center: (i16, i16),
+    /// 8.8 fixed point, bigger is smaller
+    pub scale: Curve,
+    /// 0x10000 is a full turn
+    pub angle: Curve,
+}
+
+/// moves one of the backgrounds around by itself
+#[derive(Copy, Clone)]
+#[cfg_attr(not(target_arch = "arm"), derive(Debug))]
+pub struct ScrollPath {
+    pub bg: u8,
+    pub x: Curve,
+    pub y: Curve,
+    /// if `Some`, the camera's offsets get shifted right by this much and added on,
+    /// e.g. 0 to stay put in the world or 1 to follow at half speed
+    pub camera_shift: Option<u8>,
+}
+
+/// everything the runtime needs to animate a world's skybox, without it knowing which
+/// world it is
+#[derive(Copy, Clone)]
+pub struct SkyboxAnimation {
+    pub affine: Option<SkyboxAffine>,
+    pub scroll: &'static [ScrollPath],
+    /// crossfade between the skybox (BG2) and the world's background (BG1), from 0 to 32.
+    /// whichever's more than halfway in gets drawn behind the textbox mesh.
+    pub alpha: Option<Curve>,
+}
+
+impl SkyboxAnimation {
+    pub const NONE: SkyboxAnimation = SkyboxAnimation {
+        affine: None,
+        scroll: &[],
+        alpha: None,
+    };
 }
 
 #[cfg_attr(not(target_arch = "arm"), derive(Debug))]
@@ -117,7 +285,8 @@ pub struct WorldData {
     pub bg_layer: Option<Layer>,
     pub fg_layer: Option<Layer>,
     pub skybox_layer: Option<Layer>,
-    // TODO: pub anim_tiles: { Grid }
+    pub anim_tiles: AnimTiles,
+    pub skybox_anim: SkyboxAnimation,
     // TODO: this'll have to be referential rather than a copy of the data.
     //  (some songs like Mitra's theme are used in multiple places, plus DEBUG has a soundtest)
     pub music: TrackList,
@@ -164,8 +333,14 @@ mod debug_impls_for_build_const {
             match self {
                 RoomData::Text(data) => write!(f, "RoomData::Text(&{:?})", data),
                 RoomData::Affine(data) => write!(f, "RoomData::Affine(&{:?})", data),
-                RoomData::TextLz77(data) => {
-                    writeln!(f, "RoomData::TextLz77(&[")?;
+                RoomData::Text8bpp(data) => write!(f, "RoomData::Text8bpp(&{:?})", data),
+                RoomData::TextLz77(data) | RoomData::Text8bppLz77(data) | RoomData::AffineLz77(data) => {
+                    let name = match self {
+                        RoomData::TextLz77(_) => "TextLz77",
+                        RoomData::Text8bppLz77(_) => "Text8bppLz77",
+                        _ => "AffineLz77",
+                    };
+                    writeln!(f, "RoomData::{}(&[", name)?;
                     for d in *data {
                         writeln!(f, "    &{:?},", *d)?;
                     }
@@ -175,6 +350,57 @@ mod debug_impls_for_build_const {
         }
     }
 
+    impl core::fmt::Debug for AnimTile {
+        fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+            write!(
+                f,
+                "AnimTile {{ tile_num: {}, frames_per_step: {}, frames: &{:?} }}",
+                self.tile_num, self.frames_per_step, self.frames
+            )
+        }
+    }
+
+    impl core::fmt::Debug for AnimTiles {
+        fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+            writeln!(f, "AnimTiles(&[")?;
+            for anim in self.0 {
+                writeln!(f, "    {:?},", anim)?;
+            }
+            write!(f, "])")
+        }
+    }
+
+    impl core::fmt::Debug for Curve {
+        fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+            match self {
+                Curve::Constant(x) => write!(f, "Curve::Constant({})", x),
+                Curve::Linear { start, per_frame } => {
+                    write!(f, "Curve::Linear {{ start: {}, per_frame: {} }}", start, per_frame)
+                }
+                Curve::Triangle { peak, frames_per_step } => write!(
+                    f,
+                    "Curve::Triangle {{ peak: {}, frames_per_step: {} }}",
+                    peak, frames_per_step
+                ),
+                Curve::Table { values, frames_per_step } => write!(
+                    f,
+                    "Curve::Table {{ values: &{:?}, frames_per_step: {} }}",
+                    values, frames_per_step
+                ),
+            }
+        }
+    }
+
+    impl core::fmt::Debug for SkyboxAnimation {
+        fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+            write!(
+                f,
+                "SkyboxAnimation {{ affine: {:?}, scroll: &{:?}, alpha: {:?} }}",
+                self.affine, self.scroll, self.alpha
+            )
+        }
+    }
+
     impl core::fmt::Debug for WorldPalettes {
         fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
             writeln!(f, "WorldPalettes {{")?;
@@ -189,15 +415,52 @@ mod debug_impls_for_build_const {
                 "    textbox_blend_palette: {:?},",
                 self.textbox_blend_palette
             )?;
+            writeln!(f, "    cycles: &[")?;
+            for cycle in self.cycles.iter() {
+                writeln!(f, "        {:?},", cycle)?;
+            }
+            writeln!(f, "    ],")?;
             write!(f, "}}")
         }
     }
 
+    impl core::fmt::Debug for PaletteCycle {
+        fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+            write!(
+                f,
+                "PaletteCycle {{ start: {}, len: {}, frames_per_step: {}, kind: {:?} }}",
+                self.start, self.len, self.frames_per_step, self.kind
+            )
+        }
+    }
+
+    impl core::fmt::Debug for PaletteCycleKind {
+        fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+            match self {
+                PaletteCycleKind::Rotate => write!(f, "PaletteCycleKind::Rotate"),
+                PaletteCycleKind::PingPong => write!(f, "PaletteCycleKind::PingPong"),
+                PaletteCycleKind::Keyframes(colors) => {
+                    write!(f, "PaletteCycleKind::Keyframes({:?})", colors)
+                }
+            }
+        }
+    }
+
+    impl core::fmt::Debug for KeyframeColors {
+        fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+            write!(f, "KeyframeColors {{ normal: &{:?}, blended: &[", self.normal)?;
+            for colors in self.blended {
+                write!(f, "&{:?}, ", colors)?;
+            }
+            write!(f, "], textbox: &{:?} }}", self.textbox)
+        }
+    }
+
     impl core::fmt::Debug for Sound {
         fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
             match self {
-                Sound::RawPcm8(data) => write!(f, "Sound::RawPcm8(&{:?})", data),
-                Sound::Flac(data) => write!(f, "Sound::Flac(&{:?})", data),
+                Sound::RawPcm8(data, lp) => write!(f, "Sound::RawPcm8(&{:?}, {:?})", data, lp),
+                Sound::Flac(data, lp) => write!(f, "Sound::Flac(&{:?}, {:?})", data, lp),
             }
         }
     }
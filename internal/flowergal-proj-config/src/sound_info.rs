@@ -30,28 +30,15 @@ pub const TIMER_VALUE: u16 = (0x10000 - SAMPLE_TIME) as u16;
 // must be a multiple of 8 for our handwritten ASM routines to work
 const_assert_eq!(PLAYBUF_SIZE & 0x7, 0);
 
+/// effective output resolution of the mixer: the top 8 bits go to FIFO A, and FIFO B gets the
+/// same plus the next bit down, so their sum on the DAC carries 9 bits.
+pub const MIXER_BITS: u32 = 9;
+
 #[cfg_attr(not(target_arch = "arm"), derive(Clone))]
 pub struct TrackList(pub &'static [MusicId]);
 
-/// music index in the DEBUG jukebox
-#[repr(usize)]
-#[cfg_attr(not(target_arch = "arm"), derive(Debug))]
-#[derive(Copy, Clone)]
-pub enum MusicId {
-    TomsDiner,
-}
-
-pub const SONG_FILES: &[&str] = &[
-    "Tom's Diner [Long Version] DNA feat. Suzanne Vega (1990)-32ZTjFW2RYo.mkv",
-];
-
-/// sfx index in the jukebox
-#[allow(non_camel_case_types)]
-#[cfg_attr(not(target_arch = "arm"), derive(Debug))]
-#[derive(Copy, Clone)]
-pub enum SfxId {}
-
-pub const SFX_FILES: &[&str] = &[];
+// MusicId and SfxId, generated from assets/sound_manifest.toml
+build_const!("sound_ids_bc");
 
 #[cfg(not(target_arch = "arm"))]
 mod impl_debug_for_build_const {
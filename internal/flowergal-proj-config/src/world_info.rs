@@ -1,14 +1,26 @@
-use crate::resources::ColorEffectType;
+use crate::resources::{ColorEffectType, Curve, PaletteCycleSource, ScrollPath, SkyboxAffine, SkyboxAnimation};
 use crate::sound_info::{MusicId, TrackList};
 
 pub struct WorldResourceInfo {
     pub id: WorldId,
     pub name: &'static str,
     pub tilemap_path: &'static str,
+    /// one 240-color palette for the main & foreground layers instead of 16-color palbanks,
+    /// e.g. for photos.  only half as many unique tiles fit, and the skybox (if any) has to
+    /// be affine.  no animated tiles yet.
+    pub main_8bpp: bool,
     pub skybox_path: Option<&'static str>,
+    pub skybox_anim: SkyboxAnimation,
     pub effect_path: Option<&'static str>,
     pub effect_type: ColorEffectType,
+    /// by the source art's colors, so they don't depend on how the palette gets packed
+    pub palette_cycles: &'static [PaletteCycleSource],
+    /// each row of tiles is one animation: first column as it appears in the map, then the
+    /// frames after it.  see `ImageBank::process_anim_tiles`
     pub anim_path: Option<&'static str>,
+    /// how many frames each step of each row of `anim_path` lasts.  rows past the end of this
+    /// use the last one.
+    pub anim_timing: &'static [u8],
     pub minimap_path: Option<&'static str>,
     pub songs: TrackList,
 }
@@ -26,10 +38,29 @@ pub const WORLD_RESOURCE_INFO: &[WorldResourceInfo] = &[
         id: WorldId::TomsDiner,
         name: "TOMS_DINER",
         tilemap_path: "aaker-4gvSHtHgOx4-unsplash.png", // https://unsplash.com/photos/4gvSHtHgOx4
+        main_8bpp: false,
         skybox_path: Some("amanda-dalbjorn-fvInY-Gh7sc-unsplash.png"), // https://unsplash.com/photos/fvInY-Gh7sc
+        // a windmill, turning slowly behind the diner as it fades in and out
+        skybox_anim: SkyboxAnimation {
+            affine: Some(SkyboxAffine {
+                data_center: (64, 64),
+                display_center: (120, 80),
+                scale: Curve::Constant(0b10010000),
+                angle: Curve::Linear { start: 0, per_frame: 1 << 7 },
+            }),
+            scroll: &[ScrollPath {
+                bg: 1,
+                x: Curve::Triangle { peak: 512, frames_per_step: 1 },
+                y: Curve::Constant(0),
+                camera_shift: Some(0),
+            }],
+            alpha: Some(Curve::Triangle { peak: 32, frames_per_step: 8 }),
+        },
         effect_path: Some("overlay.png"),
         effect_type: ColorEffectType::Overlay,
+        palette_cycles: &[],
         anim_path: None,
+        anim_timing: &[],
         minimap_path: Some("Minimap_Apartment.csv"),
         songs: TrackList(&[MusicId::TomsDiner]),
     },
@@ -32,6 +32,8 @@ pub trait PlayableSound {
     fn looping(&self) -> bool;
     fn data_ptr(&self) -> *const u8;
     fn reset(&mut self);
+    /// samples decoded since the sound started, counting every time through the loop
+    fn samples_elapsed(&self) -> usize;
 
     fn finished(&self) -> bool {
         self.remaining_samples() == 0 && !self.looping()
@@ -45,11 +47,10 @@ pub enum RuntimeSoundData {
 
 impl From<&Sound> for RuntimeSoundData {
     fn from(s: &Sound) -> Self {
-        // FIXME: assumes FLAC is looping and raw PCM is not
         assert_eq!(s.data_ptr() as usize & 3, 0);
         match s {
-            Sound::RawPcm8(data) => RuntimeSoundData::RawPcm8(RawPcm8::new(data, false)),
-            Sound::Flac(data) => RuntimeSoundData::Flac(SimpleFlac::new(data, true)),
+            Sound::RawPcm8(data, lp) => RuntimeSoundData::RawPcm8(RawPcm8::new(data, *lp)),
+            Sound::Flac(data, lp) => RuntimeSoundData::Flac(SimpleFlac::new(data, *lp)),
         }
     }
 }
@@ -222,7 +223,10 @@ impl AudioDriver {
 
     fn remove_sound(&mut self, index: usize) {
         self.sounds.swap_remove(index);
-        if let Some(x) = self.cur_bgm.as_mut() {
+        if self.cur_bgm == Some(index) {
+            // a bgm without a loop point ran out
+            self.cur_bgm = None;
+        } else if let Some(x) = self.cur_bgm.as_mut() {
             if *x == self.sounds.len() {
                 *x = index;
             }
@@ -248,6 +252,17 @@ impl AudioDriver {
         self.cur_bgm = Some(self.sounds.len() - 1);
     }
 
+    pub fn stop_bgm(&mut self) {
+        if let Some(index) = self.cur_bgm.take() {
+            self.remove_sound(index);
+        }
+    }
+
+    pub fn bgm_samples_elapsed(&self) -> Option<usize> {
+        self.cur_bgm
+            .map(|index| unsafe { self.sounds.get_unchecked(index) }.samples_elapsed())
+    }
+
     pub fn play_sfx(&mut self, sound: &Sound) {
         if self.sounds.len() == self.sounds.capacity() {
             self.remove_stale_sound();
@@ -318,8 +333,15 @@ impl AudioDriver {
         let start = super::timers::GbaTimer::get_ticks();
 
         let mut mix_buffer = [0i32; PLAYBUF_SIZE];
-        for sound in self.sounds.iter_mut() {
-            sound.mix_into(&mut mix_buffer);
+        // bgm goes first no matter where it sits in `sounds`, since the flac decoder
+        // overwrites the buffer instead of adding to it
+        if let Some(index) = self.cur_bgm {
+            unsafe { self.sounds.get_unchecked_mut(index) }.mix_into(&mut mix_buffer);
+        }
+        for (index, sound) in self.sounds.iter_mut().enumerate() {
+            if self.cur_bgm != Some(index) {
+                sound.mix_into(&mut mix_buffer);
+            }
         }
 
         let decoded = super::timers::GbaTimer::get_ticks();
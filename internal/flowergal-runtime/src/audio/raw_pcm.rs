@@ -1,38 +1,37 @@
 use crate::audio::PlayableSound;
+use flowergal_proj_config::resources::LoopPoint;
 
 pub struct RawPcm8 {
     pub(crate) data: &'static [u8],
     /// decode position in 8-bit samples
     decode_position: usize,
+    /// unlike decode_position, doesn't go back when we loop
+    samples_elapsed: usize,
     sample_count: usize,
     looping: bool,
+    loop_start: usize,
 }
 
 impl RawPcm8 {
-    pub const fn new(data: &'static [u8], looping: bool) -> Self {
+    pub const fn new(data: &'static [u8], loop_point: Option<LoopPoint>) -> Self {
+        let (looping, loop_start) = match loop_point {
+            Some(lp) => (true, lp.sample as usize),
+            None => (false, 0),
+        };
         RawPcm8 {
             data,
             decode_position: 0,
+            samples_elapsed: 0,
             sample_count: data.len(),
             looping,
+            loop_start,
         }
     }
-}
 
-impl PlayableSound for RawPcm8 {
+    /// mixes `mixbuf.len()` samples from `decode_position` on, without advancing it
     #[link_section = ".iwram"]
-    fn mix_into(&mut self, mixbuf: &mut [i32]) {
-        let mut remaining = self.remaining_samples();
-        if remaining == 0 {
-            if self.looping() {
-                self.reset();
-                remaining = self.remaining_samples();
-            } else {
-                return;
-            }
-        }
-        let to_decode = mixbuf.len().min(remaining);
-        for i in 0..(to_decode / 8) {
+    fn mix_chunk(&self, mixbuf: &mut [i32]) {
+        for i in 0..(mixbuf.len() / 8) {
             unsafe {
                 asm!(
                 "ldmia r12, {{r0-r1}}", // load eight 8-bit samples
@@ -70,17 +69,29 @@ impl PlayableSound for RawPcm8 {
                 options(nostack));
             }
         }
-        self.decode_position += to_decode;
-        /*
-        let len = mixbuf.len().min(remaining);
-        for mb in mixbuf[0..len].iter_mut() {
-            *mb += self.decode_sample() as i32;
-        }
-        if len == remaining && self.looping() {
-            // chance to loop a song
-            self.mix_into(&mut mixbuf[len..]);
+    }
+}
+
+impl PlayableSound for RawPcm8 {
+    #[link_section = ".iwram"]
+    fn mix_into(&mut self, mixbuf: &mut [i32]) {
+        let mut mixed = 0;
+        while mixed < mixbuf.len() {
+            let mut remaining = self.remaining_samples();
+            if remaining == 0 && self.looping() {
+                // seamless loop: buildtools pads loops to multiples of 8, so this stays aligned
+                self.reset();
+                remaining = self.remaining_samples();
+            }
+            if remaining == 0 {
+                return;
+            }
+            let to_decode = (mixbuf.len() - mixed).min(remaining);
+            self.mix_chunk(&mut mixbuf[mixed..mixed + to_decode]);
+            self.decode_position += to_decode;
+            self.samples_elapsed += to_decode;
+            mixed += to_decode;
         }
-        */
     }
 
     fn remaining_samples(&self) -> usize {
@@ -96,6 +107,10 @@ impl PlayableSound for RawPcm8 {
     }
 
     fn reset(&mut self) {
-        self.decode_position = 0;
+        self.decode_position = self.loop_start;
+    }
+
+    fn samples_elapsed(&self) -> usize {
+        self.samples_elapsed
     }
 }
@@ -26,11 +26,12 @@
 use core::mem::size_of;
 
 use crate::audio::PlayableSound;
+use flowergal_proj_config::resources::LoopPoint;
 #[cfg(feature = "verify_asm")] use core::mem::MaybeUninit;
 #[cfg(feature = "verify_asm")] use crate::audio::PLAYBUF_SIZE;
 
 // largest would be 12 'cause we're using "Subset" compliant files,
-// but we're hardcoding it to 4 in the build script for optimization-path reasons.
+// but flowergal-buildtools' encoder caps it at 4 for optimization-path reasons.
 type CoefsVec<T> = heapless::Vec<T, heapless::consts::U4>;
 
 pub struct SimpleFlac {
@@ -44,16 +45,19 @@ pub struct SimpleFlac {
     reset_encoded_position: usize,
     reset_bitbuffer: u32,
     reset_bitbufferlen: usize,
+    reset_samples_played: usize,
 
     sample_count: usize,
     sample_depth: u32,
     samples_played: usize,
+    /// unlike samples_played, doesn't go back when we loop
+    samples_elapsed: usize,
 
     looping: bool,
 }
 
 impl SimpleFlac {
-    pub fn new(data: &'static [u8], looping: bool) -> Self {
+    pub fn new(data: &'static [u8], loop_point: Option<LoopPoint>) -> Self {
         let mut flac = SimpleFlac {
             data: unsafe {
                 &*core::ptr::slice_from_raw_parts(data.as_ptr() as *const u32, (data.len() + 3) / 4)
@@ -64,18 +68,37 @@ impl SimpleFlac {
             reset_encoded_position: 0,
             reset_bitbuffer: 0,
             reset_bitbufferlen: 0,
+            reset_samples_played: 0,
             sample_count: 0,
             sample_depth: 0,
             samples_played: 0,
-            looping,
+            samples_elapsed: 0,
+            looping: loop_point.is_some(),
         };
         flac.initialize();
+        let (bitbuffer, bitbufferlen, encoded_position) =
+            (flac.bitbuffer, flac.bitbufferlen, flac.encoded_position);
+        if let Some(lp) = loop_point {
+            flac.seek_to_byte(lp.byte_offset as usize);
+            flac.reset_samples_played = lp.sample as usize;
+        }
         flac.reset_bitbuffer = flac.bitbuffer;
         flac.reset_bitbufferlen = flac.bitbufferlen;
         flac.reset_encoded_position = flac.encoded_position;
+        flac.bitbuffer = bitbuffer;
+        flac.bitbufferlen = bitbufferlen;
+        flac.encoded_position = encoded_position;
         flac
     }
 
+    /// jump to a frame boundary, as given by buildtools
+    fn seek_to_byte(&mut self, offset: usize) {
+        self.encoded_position = offset / 4;
+        self.bitbuffer = 0;
+        self.bitbufferlen = 0;
+        self.read_uint((offset & 3) * 8);
+    }
+
     #[link_section = ".iwram"]
     fn align_to_byte(&mut self) {
         self.bitbufferlen -= self.bitbufferlen & 7
@@ -980,9 +1003,10 @@ impl PlayableSound for SimpleFlac {
                 debug!("Resetting playback!");
                 self.reset();
             }
-            // HACK: doesn't "mix", overwrites mixbuf entirely, but BGM always first in line so w/e
+            // HACK: doesn't "mix", overwrites mixbuf entirely, but the mixer always does BGM first
             self.decode_frame(mixbuf);
             self.samples_played += mixbuf.len();
+            self.samples_elapsed += mixbuf.len();
             debug!("samples played: {} count: {}", self.samples_played, self.sample_count);
 
             // normalize sample depth to 16-bit
@@ -1070,6 +1094,10 @@ impl PlayableSound for SimpleFlac {
         self.bitbuffer = self.reset_bitbuffer;
         self.bitbufferlen = self.reset_bitbufferlen;
         self.encoded_position = self.reset_encoded_position;
-        self.samples_played = 0;
+        self.samples_played = self.reset_samples_played;
+    }
+
+    fn samples_elapsed(&self) -> usize {
+        self.samples_elapsed
     }
 }
@@ -29,6 +29,7 @@ pub mod logging;
 pub mod audio;
 pub mod interrupt_service;
 pub mod memory;
+pub mod platform;
 pub mod render;
 pub mod timers;
 
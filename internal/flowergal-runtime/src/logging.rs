@@ -26,7 +26,9 @@ pub fn internal_write_log(mgba: &mut MGBADebug, args: Arguments) {
 macro_rules! log {
     (target: $target:expr, $lvl:expr, $message:expr) => ({
         let lvl = $lvl;
-        if lvl as u16 <= $crate::logging::STATIC_MAX_LEVEL as u16 {
+        if lvl as u16 <= $crate::logging::STATIC_MAX_LEVEL as u16
+            && $crate::platform::Platform::current().capabilities().debug_log
+        {
             if let Some(mut mgba) = gba::mgba::MGBADebug::new() {
                 $crate::logging::internal_write_log(&mut mgba, format_args!(
                     "[{}] ({}) {}",
@@ -40,7 +42,9 @@ macro_rules! log {
     });
     (target: $target:expr, $lvl:expr, $($arg:tt)+) => ({
         let lvl = $lvl;
-        if lvl as u16 <= $crate::logging::STATIC_MAX_LEVEL as u16 {
+        if lvl as u16 <= $crate::logging::STATIC_MAX_LEVEL as u16
+            && $crate::platform::Platform::current().capabilities().debug_log
+        {
             if let Some(mut mgba) = gba::mgba::MGBADebug::new() {
                 $crate::logging::internal_write_log(&mut mgba, format_args!(
                     "[{}] ({}) ",
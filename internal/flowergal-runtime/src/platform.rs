@@ -0,0 +1,190 @@
+use gba::io::display::{DISPSTAT, VCOUNT};
+use gba::io::dma::DMA3;
+use gba::io::irq::{IrqEnableSetting, IME};
+use gba::io::keypad::read_key_input;
+use gba::io::timers::{TimerControlSetting, TimerTickRate, TM1CNT_H, TM1CNT_L};
+use gba::mgba::MGBADebug;
+
+/// where No$GBA keeps its emulator ID string
+const NOCASH_ID_ADDR: usize = 0x04FF_FA00;
+/// No$GBA's debug message port
+const NOCASH_MSG_ADDR: usize = 0x04FF_FA10;
+/// what SWI 0x0D gives back with Nintendo's BIOS, on a GBA and on a DS respectively
+const BIOS_CHECKSUM_GBA: u32 = 0xBAAE_187F;
+const BIOS_CHECKSUM_NDS: u32 = 0xBAAE_1880;
+/// hardware raises the hblank flag 1006 cycles into the line (46 after the visible part
+/// ends, see GBATEK), where a lot of emulators raise it at 960.  this is halfway between.
+const HBLANK_FLAG_LATE_ENOUGH: u16 = 983;
+
+/// what we think we're running on.  rendering & logging should ask `capabilities` rather
+/// than matching on this, so adding a platform only means filling in its row.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Platform {
+    Hardware,
+    /// GBA on a GameCube.  really is hardware, but on a TV
+    GameBoyPlayer,
+    /// DS homebrew running the game on the ARM9
+    GbaRunner2,
+    MGBA,
+    VBA,
+    NoCash,
+    NanoBoyAdvance,
+    /// or bsnes, which shares its GBA core
+    Higan,
+}
+
+static mut DETECTED: Option<Platform> = None;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Capabilities {
+    /// whether alternating frames can be expected to blend together, be it the LCD's
+    /// ghosting or an emulator option (see the README)
+    pub interframe_blending: bool,
+    /// whether mGBA's debug log registers are there for `log!` to write to
+    pub debug_log: bool,
+    /// whether the vcount interrupt fires early enough in the scanline that we can wait on
+    /// timer1 for the hblank.  if not, scanline effects start right away instead.
+    pub accurate_vcount: bool,
+}
+
+impl Platform {
+    pub fn capabilities(self) -> Capabilities {
+        let (interframe_blending, debug_log, accurate_vcount) = match self {
+            Platform::Hardware => (true, false, true),
+            // if the "Sharpness" option's set to "Soft"
+            Platform::GameBoyPlayer => (true, false, true),
+            // the DS's screen ghosts too, but the ppu is emulated on the ARM9, so the
+            // hblank timing is only approximate
+            Platform::GbaRunner2 => (true, false, false),
+            // HACK: workaround for https://github.com/mgba-emu/mgba/issues/1996
+            Platform::MGBA => (true, true, false),
+            Platform::VBA => (true, false, false),
+            Platform::NoCash => (false, false, true),
+            Platform::NanoBoyAdvance => (false, false, true),
+            // it has interframe blending, but the scanline effects flicker regardless
+            Platform::Higan => (true, false, false),
+        };
+        Capabilities { interframe_blending, debug_log, accurate_vcount }
+    }
+
+    /// detected the first time it's asked for (which may well be from a `log!`)
+    pub fn current() -> Platform {
+        unsafe {
+            if DETECTED.is_none() {
+                DETECTED = Some(Self::detect());
+            }
+            DETECTED.unwrap()
+        }
+    }
+
+    /// best guess from poking at things emulators tend to get wrong (or announce).  any
+    /// of these can be fooled, so nothing should rely on this for more than workarounds.
+    fn detect() -> Platform {
+        if MGBADebug::new().is_some() {
+            return Platform::MGBA;
+        }
+
+        // FIXME: detect No$GBA debugger? imperfect, can be disabled, detection doesn't work yet
+        unsafe {
+            let nocash_id = core::slice::from_raw_parts(NOCASH_ID_ADDR as *const u8, 16);
+            if nocash_id[2] == b'$' {
+                (NOCASH_MSG_ADDR as *mut *const u8).write_volatile("hello".as_ptr());
+                return Platform::NoCash;
+            }
+        }
+
+        // DMA can't read the BIOS, so on hardware it gets the last thing it transferred.
+        // VBA hands back zero instead.
+        unsafe {
+            let rom_src: &[u32] = &[0x900dc0de];
+            let bios_src: &[u32] = core::slice::from_raw_parts(core::ptr::null(), 1);
+            let mut dest: [u32; 2] = [!0, !0];
+            DMA3::copy_slice_to_address(rom_src, dest.as_mut_ptr() as usize);
+            DMA3::copy_slice_to_address(bios_src, dest.as_mut_ptr().add(1) as usize);
+            if dest[1] == 0 {
+                return Platform::VBA;
+            }
+        }
+
+        if cpu_has_q_flag() {
+            return Platform::GbaRunner2;
+        }
+
+        // the player holds all four directions at once to say hi, which no real d-pad can.
+        // (only once its logo's been shown, which we don't do yet, so this is for loaders)
+        let keys = read_key_input();
+        if keys.up() && keys.down() && keys.left() && keys.right() {
+            return Platform::GameBoyPlayer;
+        }
+
+        // everything above that takes a BIOS file either wants Nintendo's or HLEs this call,
+        // but NanoBoyAdvance will happily boot its own open source replacement
+        let checksum = bios_checksum();
+        if checksum != BIOS_CHECKSUM_GBA && checksum != BIOS_CHECKSUM_NDS {
+            return Platform::NanoBoyAdvance;
+        }
+
+        // cycle-accurate enough otherwise, but its hblank comes up on time with the picture.
+        // (this is also what throws off its scanline effects)
+        if hblank_flag_delay() < HBLANK_FLAG_LATE_ENOUGH {
+            return Platform::Higan;
+        }
+
+        Platform::Hardware
+    }
+}
+
+#[instruction_set(arm::a32)]
+fn bios_checksum() -> u32 {
+    let checksum: u32;
+    unsafe {
+        asm!(
+            "swi 0x0D0000",
+            lateout("r0") checksum,
+            lateout("r1") _,
+            lateout("r2") _,
+            lateout("r3") _,
+            lateout("r12") _,
+            options(nomem),
+        );
+    }
+    checksum
+}
+
+/// cycles from VCOUNT ticking over to the hblank flag going up.  in iwram so the polling
+/// loops only add a handful of cycles of slop.
+#[link_section = ".iwram"]
+#[instruction_set(arm::a32)]
+fn hblank_flag_delay() -> u16 {
+    let ime = IME.read();
+    IME.write(IrqEnableSetting::IRQ_NO);
+    let line = VCOUNT.read();
+    while VCOUNT.read() == line {}
+    TM1CNT_L.write(0);
+    TM1CNT_H.write(TimerControlSetting::new().with_tick_rate(TimerTickRate::CPU1).with_enabled(true));
+    while !DISPSTAT.read().hblank_flag() {}
+    let cycles = TM1CNT_L.read();
+    TM1CNT_H.write(TimerControlSetting::new());
+    IME.write(ime);
+    cycles
+}
+
+/// the Q flag only exists from ARMv5TE on, so writes to it don't stick on the GBA's ARM7TDMI
+#[instruction_set(arm::a32)]
+fn cpu_has_q_flag() -> bool {
+    let cpsr: u32;
+    unsafe {
+        asm!(
+            "mrs {tmp}, cpsr",
+            "orr {tmp}, {tmp}, #0x08000000",
+            "msr cpsr_f, {tmp}",
+            "mrs {cpsr}, cpsr",
+            "bic {tmp}, {cpsr}, #0x08000000",
+            "msr cpsr_f, {tmp}",
+            tmp = out(reg) _,
+            cpsr = out(reg) cpsr,
+            options(nomem, nostack),
+        );
+    }
+    cpsr & (1 << 27) != 0
+}
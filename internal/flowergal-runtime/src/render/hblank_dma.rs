@@ -0,0 +1,180 @@
+use gba::io::display::VBLANK_SCANLINE;
+use gba::io::dma::{DMAControlSetting, DMADestAddressControl, DMASrcAddressControl, DMAStartTiming, DMA0};
+
+use flowergal_proj_config::resources::AlignWrapper;
+
+use crate::render::sprites::AffineMatrix;
+
+const LINES: usize = VBLANK_SCANLINE as usize;
+/// halfwords per scanline for the biggest target (`Affine`)
+const MAX_STRIDE: usize = 8;
+/// the hblank after the last scanline still fires a transfer, so there's one spare line
+const TABLE_LEN: usize = MAX_STRIDE * (LINES + 1);
+
+const BG0HOFS_ADDR: usize = 0x0400_0010;
+const BG2PA_ADDR: usize = 0x0400_0020;
+const WIN0H_ADDR: usize = 0x0400_0040;
+
+#[link_section = ".ewram"]
+static mut HBLANK_TABLES: [AlignWrapper<[u16; TABLE_LEN]>; 2] =
+    [AlignWrapper([0; TABLE_LEN]), AlignWrapper([0; TABLE_LEN])];
+
+/// which registers get a new value every scanline
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum HblankTarget {
+    /// BGxHOFS of this background, e.g. for heat-haze waves or parallax
+    Hofs(u8),
+    /// BGxVOFS of this background
+    Vofs(u8),
+    /// both BGxHOFS and BGxVOFS
+    Scroll(u8),
+    /// BGxPA-PD, BGxX and BGxY of affine background 2 or 3, e.g. for a mode 7 floor
+    Affine(u8),
+    /// the left & right edges of WIN0 (0) or WIN1 (1), for `WindowShape::Scanlines`
+    WindowH(u8),
+}
+
+impl HblankTarget {
+    fn dest_addr(self) -> usize {
+        match self {
+            HblankTarget::Hofs(bg) | HblankTarget::Scroll(bg) => BG0HOFS_ADDR + bg as usize * 4,
+            HblankTarget::Vofs(bg) => BG0HOFS_ADDR + bg as usize * 4 + 2,
+            HblankTarget::Affine(bg) => BG2PA_ADDR + (bg as usize - 2) * 0x10,
+            HblankTarget::WindowH(win) => WIN0H_ADDR + win as usize * 2,
+        }
+    }
+
+    /// halfwords written per scanline
+    fn stride(self) -> usize {
+        match self {
+            HblankTarget::Hofs(_) | HblankTarget::Vofs(_) | HblankTarget::WindowH(_) => 1,
+            HblankTarget::Scroll(_) => 2,
+            HblankTarget::Affine(_) => MAX_STRIDE,
+        }
+    }
+}
+
+/// streams a table of per-scanline register values in with DMA0 during every hblank.
+/// the game fills in the back table while the front one's being shown, then `commit`s it
+/// to have them trade places at the next vblank.
+///
+/// only one of these can run at a time (the sound FIFOs have DMA1 & 2, and DMA3 is for copies).
+pub struct HblankDma {
+    target: Option<HblankTarget>,
+    front: usize,
+    pending: bool,
+}
+
+impl HblankDma {
+    pub const fn new() -> Self {
+        HblankDma {
+            target: None,
+            front: 0,
+            pending: false,
+        }
+    }
+
+    /// from the next vblank on, using whatever's in the front table.  the tables are laid
+    /// out differently for each target, so start it before filling one in and `commit`ting.
+    pub fn start(&mut self, target: HblankTarget) {
+        match target {
+            HblankTarget::Affine(bg) => assert!(bg == 2 || bg == 3),
+            HblankTarget::Hofs(bg) | HblankTarget::Vofs(bg) | HblankTarget::Scroll(bg) => assert!(bg < 4),
+            HblankTarget::WindowH(win) => assert!(win < 2),
+        }
+        self.target = Some(target);
+    }
+
+    /// the registers are left with the last scanline's values
+    pub fn stop(&mut self) {
+        self.target = None;
+    }
+
+    pub fn target(&self) -> Option<HblankTarget> {
+        self.target
+    }
+
+    /// `false` between a `commit` and the vblank that swaps the tables, during which the back
+    /// table shouldn't be touched.
+    pub fn ready(&self) -> bool {
+        !self.pending
+    }
+
+    pub fn commit(&mut self) {
+        self.pending = true;
+    }
+
+    /// the back table's halfwords for `line`, laid out as the target's registers are
+    pub fn line_mut(&mut self, line: usize) -> &mut [u16] {
+        let stride = self.target.map_or(MAX_STRIDE, HblankTarget::stride);
+        let table = unsafe { &mut HBLANK_TABLES[1 - self.front].0 };
+        &mut table[line * stride..(line + 1) * stride]
+    }
+
+    /// for `Hofs` or `Vofs`
+    pub fn set_offset(&mut self, line: usize, offset: u16) {
+        self.line_mut(line)[0] = offset;
+    }
+
+    /// for `Scroll`
+    pub fn set_scroll(&mut self, line: usize, hofs: u16, vofs: u16) {
+        self.line_mut(line).copy_from_slice(&[hofs, vofs]);
+    }
+
+    /// for `WindowH`.  left inclusive, right exclusive; the same for both means nothing's inside.
+    pub fn set_span(&mut self, line: usize, left: u8, right: u8) {
+        self.line_mut(line)[0] = (left as u16) << 8 | right as u16;
+    }
+
+    /// for `Affine`.  `x` and `y` are 20.8 fixed point, as the hardware wants them.
+    pub fn set_affine(&mut self, line: usize, matrix: AffineMatrix, x: i32, y: i32) {
+        self.line_mut(line).copy_from_slice(&[
+            matrix.pa as u16,
+            matrix.pb as u16,
+            matrix.pc as u16,
+            matrix.pd as u16,
+            x as u16,
+            (x >> 16) as u16,
+            y as u16,
+            (y >> 16) as u16,
+        ]);
+    }
+
+    /// only call during vblank
+    pub(crate) fn vblank(&mut self) {
+        unsafe { DMA0::set_control(DMAControlSetting::new()) };
+        if self.pending {
+            self.front = 1 - self.front;
+            self.pending = false;
+        }
+        let target = match self.target {
+            Some(target) => target,
+            None => return,
+        };
+        let stride = target.stride();
+        let dest = target.dest_addr();
+        let table = unsafe { &HBLANK_TABLES[self.front].0 };
+
+        // there's no hblank before the first scanline, so that one goes in right now
+        for (i, value) in table[..stride].iter().enumerate() {
+            unsafe { ((dest + i * 2) as *mut u16).write_volatile(*value) };
+        }
+
+        let use_32bit = stride > 1;
+        let count = if use_32bit { stride / 2 } else { 1 };
+        unsafe {
+            DMA0::set_source(table[stride..].as_ptr() as *const u32);
+            DMA0::set_dest(dest as *mut u32);
+            DMA0::set_count(count as u16);
+            DMA0::set_control(
+                DMAControlSetting::new()
+                    .with_dest_address_control(DMADestAddressControl::IncrementReload)
+                    .with_source_address_control(DMASrcAddressControl::Increment)
+                    .with_dma_repeat(true)
+                    .with_use_32bit(use_32bit)
+                    .with_start_time(DMAStartTiming::HBlank)
+                    .with_enabled(true),
+            );
+        }
+    }
+}
@@ -12,10 +12,17 @@
 ///     4. and 5: skybox, or affine 512x512 for windmill
 ///     6. hud
 ///     7. ???
-///  4. and 5. obj (subdivisions TBD)
+///  4. and 5. obj: 4 is the OBJ-window mask, 5 is for game sprites (see `sprites`)
 ///
 
+pub mod hblank_dma;
 pub mod palette;
+pub mod palette_cycle;
+pub mod scanline;
+pub mod sprites;
+pub mod tile_anim;
+pub mod transition;
+pub mod window;
 
 use core::mem::{size_of, transmute};
 
@@ -25,26 +32,41 @@ use gba::Color;
 
 use gba::io::display::{
     DisplayControlSetting, DisplayStatusSetting, MosaicSetting, DISPCNT, DISPSTAT, MOSAIC,
-    VBLANK_SCANLINE, VCOUNT,
+    VBLANK_SCANLINE,
 };
-use gba::io::window::{InsideWindowSetting, WININ, OutsideWindowSetting};
 use gba::palram::{PALRAM_BG, PALRAM_OBJ};
 use gba::oam::{ObjectAttributes, OBJAttr0, OBJAttr1, OBJAttr2, ObjectRender, ObjectMode, ObjectShape, ObjectSize};
 
 use flowergal_proj_config::resources::*;
 
+use crate::platform::Platform;
 use crate::timers::GbaTimer;
-use crate::render::palette::{NO_EFFECT, VCOUNT_SEQUENCE, VCOUNT_SEQUENCE_LEN, NO_COLORS, TEXTBOX_VCOUNTS};
+use crate::render::hblank_dma::HblankDma;
+use crate::render::palette::{NO_EFFECT, NO_COLORS};
+use crate::render::palette_cycle::{PaletteCycler, PaletteVariant};
+use crate::render::scanline::{Action, Client, ScanlineAction, ScanlineEffect, ScanlineFrames, ScanlineScheduler, MAX_TRIGGERS};
+use crate::render::sprites::ShadowOam;
+use crate::render::tile_anim::TileAnimator;
+use crate::render::transition::{Transition, TransitionEffect, TransitionPhase};
+use crate::render::window::Windows;
 use crate::memory::MemoryOps;
-use core::ops::Range;
-use gba::io::dma::DMA3;
-
-#[derive(PartialEq)]
-pub enum Platform {
-    Hardware,
-    MGBA,
-    VBA,
-    NoCash,
+
+const BG0HOFS_ADDR: usize = 0x0400_0010;
+const PERF_LOG_LEN: usize = MAX_TRIGGERS;
+
+/// in pixels
+pub const SCREEN_WIDTH: i32 = 240;
+pub const SCREEN_HEIGHT: i32 = VBLANK_SCANLINE as i32;
+
+/// how the effects that rely on the LCD's ghosting (or an emulator's interframe blending)
+/// get drawn
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum RenderMode {
+    /// alternate between two offset frames' worth of dithering for the screen to blend
+    /// together: the window mesh flips, HUD edges trade tiles, gradient bands shift by half
+    Interframe,
+    /// the same thing every frame, for displays that would show the above as flicker
+    Stable,
 }
 
 pub struct GbaRenderer {
@@ -55,18 +77,22 @@ pub struct GbaRenderer {
     /// applied during hblank at vcount=113 and reverted at vcount=153.
     /// should be computed based on palette_normal or `palette_effect[133 / PAL_FX_SCANLINES]`
     palette_textbox_rom: &'static [Color],
-    /// chosen during vcount irq, while waiting for timer1 irq.
-    next_copy_pal: &'static [Color],
-    /// workaround for next_copy_pal being insufficient alone for textbox_end when blend enabled
-    next_copy_extra_norm_range: Range<usize>,
     dispstat: DisplayStatusSetting,
     showing_textbox: bool,
     showing_effect: bool,
     pub frame_counter: u32,
+    /// how many vcount interrupts so far this frame
     vcount_index: usize,
-    perf_log: [u32; VCOUNT_SEQUENCE_LEN],
+    perf_log: [u32; PERF_LOG_LEN],
     pub platform: Platform,
-    // shadow_oam: ShadowOam,
+    render_mode: RenderMode,
+    shadow_oam: ShadowOam,
+    transition: Transition,
+    palette_cycler: PaletteCycler,
+    scanlines: ScanlineScheduler,
+    hblank_dma: HblankDma,
+    windows: Windows,
+    tile_animator: TileAnimator,
 }
 
 const fn palram_bg_slice() -> &'static mut [Color] {
@@ -93,8 +119,6 @@ impl GbaRenderer {
             palette_normal_rom: NO_COLORS,
             palette_effect_rom: NO_EFFECT,
             palette_textbox_rom: NO_COLORS,
-            next_copy_pal: NO_COLORS,
-            next_copy_extra_norm_range: 0..0,
             dispstat: DisplayStatusSetting::new()
                 .with_vblank_irq_enable(true)
                 .with_vcounter_irq_enable(true),
@@ -102,54 +126,29 @@ impl GbaRenderer {
             showing_effect: false,
             frame_counter: 0,
             vcount_index: 0,
-            perf_log: [0; VCOUNT_SEQUENCE_LEN],
+            perf_log: [0; PERF_LOG_LEN],
             platform: Platform::Hardware,
+            render_mode: RenderMode::Interframe,
+            shadow_oam: ShadowOam::new(),
+            transition: Transition::new(),
+            palette_cycler: PaletteCycler::new(),
+            scanlines: ScanlineScheduler::new(),
+            hblank_dma: HblankDma::new(),
+            windows: Windows::new(),
+            tile_animator: TileAnimator::new(),
         }
     }
 
-    fn detect_platform() -> Platform {
-        if let Some(_) = gba::mgba::MGBADebug::new() {
-            return Platform::MGBA;
-        }
-
-        unsafe {
-            let rom_src: &[u32] = &[0x900dc0de];
-            let bios_src: &[u32] = core::slice::from_raw_parts(core::ptr::null(), 1);
-            let mut dest: [u32; 2] = [!0, !0];
-            DMA3::copy_slice_to_address(rom_src, dest.as_mut_ptr() as usize);
-            DMA3::copy_slice_to_address(bios_src, dest.as_mut_ptr().add(1) as usize);
-            if dest[1] == 0 {
-                return Platform::VBA;
-            }
-        }
-
-        // FIXME: detect No$GBA debugger? imperfect, can be disabled, detection doesn't work yet
-        unsafe {
-            let nocash_id = core::slice::from_raw_parts(0x4fffa00 as *const u8, 16);
-            if nocash_id[2] == '$' as u8 {
-                (0x4fffa10 as *mut *const u8).write_volatile("hello".as_ptr());
-                return Platform::NoCash;
-            }
-        }
-
-        Platform::Hardware
-    }
-
     pub fn initialize(&mut self) {
-        self.platform = Self::detect_platform();
-
-        gba::io::window::WINOUT.write(OutsideWindowSetting::new()
-            .with_outside_bg0(true)
-            .with_outside_bg1(true)
-            .with_outside_bg2(true)
-            .with_outside_bg3(true)
-            .with_outside_color_special(true)
-            .with_obj_win_bg0(true)
-            .with_obj_win_bg1(true)
-            .with_obj_win_bg2(false)
-            .with_obj_win_bg3(true)
-            .with_obj_win_color_special(true)
-        );
+        self.platform = Platform::current();
+        info!("platform: {:?}", self.platform);
+        self.set_render_mode(if self.platform.capabilities().interframe_blending {
+            RenderMode::Interframe
+        } else {
+            RenderMode::Stable
+        });
+
+        self.windows.commit();
 
         let sprite_chars = unsafe {
             let ptr = CHAR_BASE_BLOCKS.get(4).unwrap().to_usize() as *mut Tile4bpp;
@@ -170,9 +169,16 @@ impl GbaRenderer {
         PALRAM_OBJ.get(1).unwrap().write(gba::Color(0xffff));
 
         self.update_sprite_attributes();
+        self.shadow_oam.commit();
+    }
+
+    /// the game's sprites.  changes show up at the next vblank.
+    pub fn sprites(&mut self) -> &mut ShadowOam {
+        &mut self.shadow_oam
     }
 
     fn update_sprite_attributes(&mut self) {
+        let vflip = self.even_odd_frame();
         for x in 0..=2 {
             for y in 0..=2 {
                 let shape = match y {
@@ -180,7 +186,7 @@ impl GbaRenderer {
                     _ => ObjectShape::Square,
                 };
                 let slot = (y * 3 + x) as usize;
-                gba::oam::write_obj_attributes(slot, ObjectAttributes {
+                self.shadow_oam.set_window_mask_sprite(slot, ObjectAttributes {
                     attr0: OBJAttr0::new()
                         .with_row_coordinate(64 * y)
                         .with_obj_rendering(ObjectRender::Normal)
@@ -188,7 +194,7 @@ impl GbaRenderer {
                         .with_obj_shape(shape),
                     attr1: OBJAttr1::new()
                         .with_col_coordinate(64 * x + 24)
-                        .with_vflip(self.even_odd_frame())
+                        .with_vflip(vflip)
                         .with_obj_size(ObjectSize::Three),
                     attr2: OBJAttr2::new()
                         .with_tile_id(0)
@@ -202,10 +208,52 @@ impl GbaRenderer {
     pub fn vblank(&mut self) {
         self.frame_counter += 1;
         self.update_sprite_attributes();
+        self.shadow_oam.commit();
+        self.transition.vblank(&mut self.windows);
+        self.windows.commit();
+        self.advance_palette_cycles();
+        self.tile_animator.vblank();
+        self.scanlines.vblank();
+        self.hblank_dma.vblank();
     }
 
+    /// the scanline copies patch up whatever they overwrite, but anything outside of them
+    /// (or everything, with no effect or textbox showing) is only ever set from here.
+    fn advance_palette_cycles(&mut self) {
+        self.palette_cycler.advance();
+        let normal = self.palette_normal_rom;
+        self.palette_cycler.patch(palram_bg_slice(), PaletteVariant::Normal, normal, normal, 0..normal.len());
+    }
+
+    /// runs over `frames` frames from the next vblank on; poll `transition_running` or
+    /// `transition_hidden` to find out when it's done.
+    pub fn start_transition(&mut self, effect: TransitionEffect, phase: TransitionPhase, frames: u16) {
+        self.transition.start(&mut self.windows, effect, phase, frames);
+    }
+
+    pub fn transition_running(&self) -> bool {
+        self.transition.is_running()
+    }
+
+    pub fn transition_hidden(&self) -> bool {
+        self.transition.is_hidden()
+    }
+
+    /// which of the two alternating frames this is.  always `false` (even) in
+    /// `RenderMode::Stable`, so anything keyed off of it holds still.
     pub fn even_odd_frame(&self) -> bool {
-        self.frame_counter & 1 != 0
+        self.render_mode == RenderMode::Interframe && self.frame_counter & 1 != 0
+    }
+
+    pub fn render_mode(&self) -> RenderMode {
+        self.render_mode
+    }
+
+    pub fn set_render_mode(&mut self, mode: RenderMode) {
+        info!("render mode: {:?}", mode);
+        self.render_mode = mode;
+        self.scanlines.set_alternating(mode == RenderMode::Interframe);
+        self.update_palette_scanline_effects();
     }
 
     pub fn frame_counter(&self) -> u32 {
@@ -216,66 +264,160 @@ impl GbaRenderer {
     pub fn vcounter(&mut self) {
         // TODO: hit every other vcount and only set up timer1 if we're supposed to do a thing?
         // fudging the numbers a bit on this 750, but i'm assuming there'll be ~50 cycles overhead
-        let cycles = match self.platform {
-            // HACK: workaround for https://github.com/mgba-emu/mgba/issues/1996
+        let cycles = if self.platform.capabilities().accurate_vcount {
+            750
+        } else {
             // start copying much sooner so it gets done *before* the next hdraw starts.
-            Platform::MGBA | Platform::VBA => 50,
-            _ => 750,
+            50
         };
         GbaTimer::setup_timer1_irq(cycles);
-
-        let mut vcount = VCOUNT.read();
-        if vcount >= VBLANK_SCANLINE /*- BLEND_RESOLUTION*/ as u16 {
-            vcount = 0;
-        }
-
-        self.next_copy_pal = if self.showing_effect && !self.palette_effect_rom.is_empty()
-            && !(self.showing_textbox && (TEXTBOX_VCOUNTS[0]..=TEXTBOX_VCOUNTS[1]).contains(&vcount)) {
-            self.palette_effect_rom[vcount as usize / BLEND_RESOLUTION].data()
-        } else if self.showing_textbox && vcount == TEXTBOX_VCOUNTS[0] {
-            self.palette_textbox_rom
-        } else if self.showing_textbox && vcount == TEXTBOX_VCOUNTS[1] {
-            if self.showing_effect && !self.palette_effect_rom.is_empty() {
-                let pal = self.palette_effect_rom[TEXTBOX_VCOUNTS[1] as usize / BLEND_RESOLUTION].data();
-                let next_line_index = (pal.len() & !15) + 16;
-                if next_line_index < self.palette_normal_rom.len() {
-                    self.next_copy_extra_norm_range = next_line_index..self.palette_normal_rom.len();
-                }
-                pal
-            } else {
-                self.palette_normal_rom
-            }
-        } else {
-            NO_COLORS
-        };
     }
 
     #[link_section = ".iwram"]
     pub fn timer1(&mut self) {
         let start = GbaTimer::get_ticks();
 
-        let pal = self.next_copy_pal;
-        palram_bg_slice()[0..pal.len()].copy_from_slice(pal);
-        if !self.next_copy_extra_norm_range.is_empty() {
-            let mut range = 0..0;
-            core::mem::swap(&mut self.next_copy_extra_norm_range, &mut range);
-            palram_bg_slice()[range.clone()].copy_from_slice(&self.palette_normal_rom[range]);
+        let (line, due) = self.scanlines.due();
+        for index in due.clone() {
+            if let Some(action) = self.scanlines.action(index) {
+                self.run_scanline_action(line, action);
+            }
         }
 
-        self.perf_log[self.vcount_index] = GbaTimer::get_ticks() - start;
+        if let Some(entry) = self.perf_log.get_mut(self.vcount_index) {
+            *entry = GbaTimer::get_ticks() - start;
+        }
         self.vcount_index += 1;
-        let mut next_vcount = VCOUNT_SEQUENCE[self.vcount_index];
-        if next_vcount == 0xFF {
+        // (vblank has already bumped the frame counter for the frame this leads into)
+        let (next_vcount, end_of_frame) = self.scanlines.advance(due, self.even_odd_frame());
+        if end_of_frame {
             #[cfg(feature = "bench_video")]
-            warn!("pal copy: {:?}", &self.perf_log[..self.vcount_index]);
+            warn!("pal copy: {:?}", &self.perf_log[..self.vcount_index.min(PERF_LOG_LEN)]);
             self.vcount_index = 0;
-            next_vcount = VCOUNT_SEQUENCE[self.vcount_index];
         }
         DISPSTAT.write(self.dispstat.with_vcount_setting(next_vcount));
     }
 
+    /// `line` is the scanline about to be drawn
+    #[link_section = ".iwram"]
+    fn run_scanline_action(&self, line: u16, action: Action) {
+        let band = line.saturating_sub(1) as usize / BLEND_RESOLUTION;
+        match action {
+            Action::Game(ScanlineAction::Palette { index, colors }) => {
+                let index = index as usize;
+                if let Some(dest) = palram_bg_slice().get_mut(index..index + colors.len()) {
+                    dest.copy_from_slice(colors);
+                }
+            }
+            Action::Game(ScanlineAction::Register { addr, value }) => unsafe {
+                (addr as *mut u16).write_volatile(value);
+            },
+            Action::Game(ScanlineAction::Scroll { bg, hofs, vofs }) => unsafe {
+                let addr = BG0HOFS_ADDR + bg as usize * 4;
+                (addr as *mut u16).write_volatile(hofs);
+                ((addr + 2) as *mut u16).write_volatile(vofs);
+            },
+            Action::Gradient => {
+                if let Some(pal) = self.palette_effect_rom.get(band) {
+                    self.copy_world_palette(pal.data(), PaletteVariant::Effect(band), 0);
+                }
+            }
+            Action::TextboxStart => {
+                self.copy_world_palette(self.palette_textbox_rom, PaletteVariant::Textbox, 0);
+            }
+            Action::TextboxEnd => {
+                if let Some(pal) = self.palette_effect_rom.get(band) {
+                    let pal = pal.data();
+                    self.copy_world_palette(pal, PaletteVariant::Effect(band), 0);
+                    // the textbox palette covers more than the blended one does
+                    let next_line_index = (pal.len() & !15) + 16;
+                    if next_line_index < self.palette_normal_rom.len() {
+                        let normal = &self.palette_normal_rom[next_line_index..];
+                        self.copy_world_palette(normal, PaletteVariant::Normal, next_line_index);
+                    }
+                } else {
+                    self.copy_world_palette(self.palette_normal_rom, PaletteVariant::Normal, 0);
+                }
+            }
+        }
+    }
+
+    /// `colors` is `variant`'s palette from `index` on
+    #[link_section = ".iwram"]
+    fn copy_world_palette(&self, colors: &[Color], variant: PaletteVariant, index: usize) {
+        let range = index..index + colors.len();
+        palram_bg_slice()[range.clone()].copy_from_slice(colors);
+        let src = match variant {
+            PaletteVariant::Normal => self.palette_normal_rom,
+            PaletteVariant::Effect(band) => self.palette_effect_rom[band].data(),
+            PaletteVariant::Textbox => self.palette_textbox_rom,
+        };
+        self.palette_cycler.patch(palram_bg_slice(), variant, src, self.palette_normal_rom, range);
+    }
+
+    /// effects the game wants at particular scanlines, alongside the world's own gradient
+    /// and textbox palette swaps.  changes take effect after the next vblank.
+    pub fn scanline_effects(&mut self) -> &mut ScanlineScheduler {
+        &mut self.scanlines
+    }
+
+    /// WIN0 & WIN1, and which layers show inside & outside them.  changes show up at the
+    /// next vblank.
+    pub fn windows(&mut self) -> &mut Windows {
+        &mut self.windows
+    }
+
+    /// per-scanline scroll or affine registers, streamed in by DMA every hblank
+    pub fn hblank_dma(&mut self) -> &mut HblankDma {
+        &mut self.hblank_dma
+    }
+
+    /// the gradient and textbox are just scanline effects like any other, re-registered
+    /// whenever either is turned on or off
+    fn update_palette_scanline_effects(&mut self) {
+        self.scanlines.remove_client(Client::Gradient);
+        self.scanlines.remove_client(Client::Textbox);
+
+        let showing_textbox = self.showing_textbox;
+        if self.showing_effect && !self.palette_effect_rom.is_empty() {
+            let res = BLEND_RESOLUTION as u16;
+            let scanlines = &mut self.scanlines;
+            let mut add = |effect| scanlines.add_for(Client::Gradient, effect);
+            add(ScanlineEffect::new(0, Action::Gradient));
+            // half a band apart on alternating frames, for flickering between the two to
+            // smooth out the horizontal bands.  (playing on emulator? turn on "interframe blending")
+            let passes: &[(u16, ScanlineFrames)] = match self.render_mode {
+                RenderMode::Interframe => &[(res + 1, ScanlineFrames::Even), (res / 2 + 1, ScanlineFrames::Odd)],
+                // every band at full height instead
+                RenderMode::Stable => &[(res + 1, ScanlineFrames::Every)],
+            };
+            for &(first, frames) in passes {
+                let gradient = |line, until| {
+                    ScanlineEffect::new(line, Action::Gradient)
+                        .with_repeat(res, until)
+                        .with_frames(frames)
+                };
+                if showing_textbox {
+                    // the textbox has its own colors the whole way down
+                    let after = TEXTBOX_Y_END + 2;
+                    let resume = first + (after - first + res - 1) / res * res;
+                    add(gradient(first, TEXTBOX_Y_START));
+                    add(gradient(resume, VBLANK_SCANLINE));
+                } else {
+                    add(gradient(first, VBLANK_SCANLINE));
+                }
+            }
+        }
+
+        if showing_textbox {
+            self.scanlines.add_for(Client::Textbox, ScanlineEffect::new(TEXTBOX_Y_START, Action::TextboxStart));
+            self.scanlines.add_for(Client::Textbox, ScanlineEffect::new(TEXTBOX_Y_END + 1, Action::TextboxEnd));
+        }
+    }
+
     pub fn set_color_effect_shown(&mut self, showing_effect: bool) {
         self.showing_effect = showing_effect;
+        self.update_palette_scanline_effects();
         if !showing_effect {
             let pal = self.palette_normal_rom;
             let effect_len = self.palette_effect_rom.first()
@@ -289,6 +431,7 @@ impl GbaRenderer {
 
     pub fn set_textbox_shown(&mut self, show: bool) {
         self.showing_textbox = show;
+        self.update_palette_scanline_effects();
         if !show {
             let pal = self.palette_normal_rom;
             let effect_len = self.palette_effect_rom.first()
@@ -310,6 +453,8 @@ impl GbaRenderer {
         self.palette_effect_rom = world_pal.blended_palettes;
         self.palette_textbox_rom = world_pal.textbox_blend_palette.data();
         self.showing_effect = !world_pal.blended_palettes.is_empty();
+        self.palette_cycler.load(world_pal.cycles);
+        self.update_palette_scanline_effects();
         self.set_normal_colors_bg(0, self.palette_normal_rom);
         palram_bg_slice()[self.palette_normal_rom.len()..240].fill(gba::Color(0));
     }
@@ -325,6 +470,11 @@ impl GbaRenderer {
         }
     }
 
+    /// the world's animated tiles, which should already be loaded into `charblock`
+    pub fn load_anim_tiles(&mut self, charblock: u16, anims: &'static [AnimTile]) {
+        self.tile_animator.load(charblock, anims);
+    }
+
     pub fn load_bg_tiles_lz77(&self, charblock: u16, data: &[u32]) {
         assert!(charblock < 4);
         assert!(data[0] >> 8 <= 256 * 8 * 8);
@@ -39,58 +39,7 @@
 ///   - 276c of 4c copies (amortized 2c/word read from ROM + 2c/word write to PalRAM)
 ///     - 276/4 = 69 words = 138 colors (minus overhead < 8.5 palette lines)
 
-use flowergal_proj_config::resources::{PaletteData, BLEND_ENTRIES, TEXTBOX_Y_START, TEXTBOX_Y_END, BLEND_RESOLUTION};
-use gba::io::display::VBLANK_SCANLINE;
-
-const ZERO: u32 = 0;
+use flowergal_proj_config::resources::PaletteData;
 
 pub(crate) const NO_EFFECT: &[PaletteData] = &[];
 pub(crate) const NO_COLORS: &[gba::Color] = &[];
-
-/// must be in sorted order
-pub const TEXTBOX_VCOUNTS: [u16; 2] = [TEXTBOX_Y_START - 1, TEXTBOX_Y_END];
-
-/// last vcount interrupt that fires on hardware
-const VCOUNT_LAST: u16 = VBLANK_SCANLINE + 68 - 1; // 227
-
-/// two passes of the screen in cycles, one with vcounts offset by half the blend resolution
-/// for flickering between palette swap lines to smooth horizontal bands.
-/// (playing on emulator? turn on "interframe blending")
-pub(crate) const VCOUNT_SEQUENCE_LEN: usize = 2 * (BLEND_ENTRIES + TEXTBOX_VCOUNTS.len() + 1);
-pub(crate) const VCOUNT_SEQUENCE: [u16; VCOUNT_SEQUENCE_LEN] = compute_vcount_sequence();
-/// used in the above array to signify that we should rewind
-const VCOUNT_INVALID: u16 = 0xFF;
-
-#[allow(clippy::comparison_chain)]
-const fn compute_vcount_sequence() -> [u16; VCOUNT_SEQUENCE_LEN] {
-    let mut vcounts = [VCOUNT_INVALID; VCOUNT_SEQUENCE_LEN];
-    let offsets: [u16; 2] = [BLEND_RESOLUTION as u16, BLEND_RESOLUTION as u16 / 2];
-    let mut oi = 0;
-    let mut vi = 0;
-
-    while oi < offsets.len() {
-        let mut line = offsets[oi];
-        let mut ti = 0;
-        while line < VBLANK_SCANLINE {
-            if ti < TEXTBOX_VCOUNTS.len() {
-                let y = TEXTBOX_VCOUNTS[ti];
-                if line > y {
-                    vcounts[vi] = y;
-                    vi += 1;
-                    ti += 1;
-                    continue;
-                } else if line == y {
-                    ti += 1;
-                }
-            }
-            vcounts[vi] = line;
-            vi += 1;
-            line += BLEND_RESOLUTION as u16;
-        }
-        vcounts[vi] = VCOUNT_LAST - 5;
-        vi += 1;
-        oi += 1;
-    }
-
-    vcounts
-}
@@ -0,0 +1,127 @@
+use core::ops::Range;
+
+use gba::Color;
+
+use flowergal_proj_config::resources::{PaletteCycle, PaletteCycleKind};
+
+/// any more than this in a world get ignored
+pub const MAX_PALETTE_CYCLES: usize = 8;
+
+/// which of the world's precomputed palettes a copy into PALRAM came from, so the cycled
+/// colors can be taken from the same one (and keep the gradient or textbox tint).
+#[derive(Copy, Clone)]
+pub enum PaletteVariant {
+    Normal,
+    /// index into `blended_palettes`
+    Effect(usize),
+    Textbox,
+}
+
+#[derive(Copy, Clone)]
+struct CycleState {
+    countdown: u8,
+    step: u16,
+}
+
+pub struct PaletteCycler {
+    cycles: &'static [PaletteCycle],
+    states: [CycleState; MAX_PALETTE_CYCLES],
+}
+
+impl PaletteCycler {
+    pub const fn new() -> Self {
+        PaletteCycler {
+            cycles: &[],
+            states: [CycleState { countdown: 0, step: 0 }; MAX_PALETTE_CYCLES],
+        }
+    }
+
+    pub fn load(&mut self, cycles: &'static [PaletteCycle]) {
+        if cycles.len() > MAX_PALETTE_CYCLES {
+            warn!("{} palette cycles, only using the first {}", cycles.len(), MAX_PALETTE_CYCLES);
+        }
+        self.cycles = &cycles[..cycles.len().min(MAX_PALETTE_CYCLES)];
+        for (state, cycle) in self.states.iter_mut().zip(self.cycles) {
+            *state = CycleState { countdown: cycle.frames_per_step, step: 0 };
+        }
+    }
+
+    /// once a frame
+    pub fn advance(&mut self) {
+        for (state, cycle) in self.states.iter_mut().zip(self.cycles) {
+            state.countdown = state.countdown.saturating_sub(1);
+            if state.countdown == 0 {
+                state.countdown = cycle.frames_per_step;
+                state.step = state.step.wrapping_add(1);
+            }
+        }
+    }
+
+    /// after `src` (of the given variant) has been copied into `palram`, puts the cycled
+    /// colors in `range` where they currently belong.  `normal` fills in for anything
+    /// `src` doesn't cover.
+    #[link_section = ".iwram"]
+    pub fn patch(
+        &self,
+        palram: &mut [Color],
+        variant: PaletteVariant,
+        src: &[Color],
+        normal: &[Color],
+        range: Range<usize>,
+    ) {
+        for (state, cycle) in self.states.iter().zip(self.cycles) {
+            let start = cycle.start as usize;
+            let len = cycle.len as usize;
+            if len == 0 || start >= range.end || start + len <= range.start {
+                continue;
+            }
+            let step = state.step as usize;
+            for j in 0..len {
+                let i = start + j;
+                if !range.contains(&i) {
+                    continue;
+                }
+                let color = match &cycle.kind {
+                    PaletteCycleKind::Rotate => {
+                        let from = start + (j + len - step % len) % len;
+                        src.get(from).or_else(|| normal.get(from))
+                    }
+                    PaletteCycleKind::PingPong => {
+                        let offset = ping_pong(step, len);
+                        let from = start + (j + len - offset) % len;
+                        src.get(from).or_else(|| normal.get(from))
+                    }
+                    PaletteCycleKind::Keyframes(keyframes) => {
+                        let frame = step % (keyframes.normal.len() / len).max(1);
+                        let table = match variant {
+                            PaletteVariant::Normal => keyframes.normal,
+                            PaletteVariant::Effect(band) => {
+                                keyframes.blended.get(band).copied().unwrap_or(keyframes.normal)
+                            }
+                            PaletteVariant::Textbox if !keyframes.textbox.is_empty() => keyframes.textbox,
+                            PaletteVariant::Textbox => keyframes.normal,
+                        };
+                        table.get(frame * len + j)
+                    }
+                };
+                if let (Some(color), Some(dest)) = (color, palram.get_mut(i)) {
+                    *dest = *color;
+                }
+            }
+        }
+    }
+}
+
+/// 0, 1, .. len-1, len-2, .. 1, 0, 1, ..
+fn ping_pong(step: usize, len: usize) -> usize {
+    if len < 2 {
+        return 0;
+    }
+    let period = 2 * (len - 1);
+    let phase = step % period;
+    if phase < len {
+        phase
+    } else {
+        period - phase
+    }
+}
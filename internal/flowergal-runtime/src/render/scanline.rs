@@ -0,0 +1,330 @@
+use core::ops::Range;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use gba::io::display::VBLANK_SCANLINE;
+use gba::Color;
+
+/// any more than this at once get refused
+pub const MAX_SCANLINE_EFFECTS: usize = 32;
+/// per frame, after repeating effects are expanded
+pub(crate) const MAX_TRIGGERS: usize = 96;
+
+/// last vcount interrupt that fires on hardware
+const VCOUNT_LAST: u16 = VBLANK_SCANLINE + 68 - 1; // 227
+/// there's no hblank before scanline 0, so its effects go in near the end of the last vblank
+const VCOUNT_FRAME_START: u16 = VCOUNT_LAST - 5;
+const NO_EFFECT: u8 = 0xFF;
+
+/// which frames an effect happens on.  the scheduler alternates between two frames' worth
+/// of triggers, lined up with `GbaRenderer::even_odd_frame` (odd being `true`), so effects
+/// can be offset between them to flicker.  in `RenderMode::Stable` there's no alternating,
+/// so `Even` and `Odd` both mean every frame.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ScanlineFrames {
+    Every,
+    Even,
+    Odd,
+}
+
+impl ScanlineFrames {
+    fn includes(self, odd: bool) -> bool {
+        match self {
+            ScanlineFrames::Every => true,
+            ScanlineFrames::Even => !odd,
+            ScanlineFrames::Odd => odd,
+        }
+    }
+}
+
+/// what to do in the hblank right before the effect's scanline
+#[derive(Copy, Clone, Debug)]
+pub enum ScanlineAction {
+    /// copy into background PALRAM starting at `index`.  (if these overwrite any of the
+    /// world's cycling colors, they stay overwritten until the next copy of the world palette)
+    Palette { index: u16, colors: &'static [Color] },
+    /// write a halfword to an IO register
+    Register { addr: usize, value: u16 },
+    /// set BGxHOFS and BGxVOFS of background `bg`
+    Scroll { bg: u8, hofs: u16, vofs: u16 },
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ScanlineEffect<A = ScanlineAction> {
+    /// first scanline drawn with the effect applied, 0 through 159
+    pub line: u16,
+    /// if nonzero, happens again every this many scanlines after `line`, up to `until`
+    pub every: u16,
+    pub until: u16,
+    pub frames: ScanlineFrames,
+    pub action: A,
+}
+
+impl<A: Copy> ScanlineEffect<A> {
+    pub fn new(line: u16, action: A) -> Self {
+        ScanlineEffect {
+            line,
+            every: 0,
+            until: VBLANK_SCANLINE,
+            frames: ScanlineFrames::Every,
+            action,
+        }
+    }
+
+    pub fn with_repeat(self, every: u16, until: u16) -> Self {
+        ScanlineEffect { every, until, ..self }
+    }
+
+    pub fn with_frames(self, frames: ScanlineFrames) -> Self {
+        ScanlineEffect { frames, ..self }
+    }
+
+    fn lines(&self) -> impl Iterator<Item = u16> {
+        let (line, every, until) = (self.line, self.every, self.until.min(VBLANK_SCANLINE));
+        let count = if every == 0 { 1 } else { (until.saturating_sub(line) + every - 1) / every };
+        (0..count.max(1)).map(move |i| line + i * every)
+    }
+}
+
+/// handle to a registered effect, for removing it later
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ScanlineEffectId(u8);
+
+/// the renderer's own effects get cleared out & re-added whenever its settings change,
+/// without disturbing the game's.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) enum Client {
+    Game,
+    Gradient,
+    Textbox,
+}
+
+/// the renderer's own actions, which need its state to know what to copy
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum Action {
+    Game(ScanlineAction),
+    /// the world's blended palette for whichever band the scanline is in
+    Gradient,
+    TextboxStart,
+    TextboxEnd,
+}
+
+#[derive(Copy, Clone)]
+struct Registered {
+    client: Client,
+    effect: ScanlineEffect<Action>,
+}
+
+#[derive(Copy, Clone)]
+struct Trigger {
+    vcount: u8,
+    effect: u8,
+}
+
+/// one frame's worth of vcounts to interrupt on, in order
+struct Pass {
+    triggers: [Trigger; MAX_TRIGGERS],
+    len: usize,
+    /// where the VCOUNT_FRAME_START ones begin
+    tail: usize,
+}
+
+impl Pass {
+    const fn new() -> Self {
+        let mut triggers = [Trigger { vcount: VCOUNT_FRAME_START as u8, effect: NO_EFFECT }; MAX_TRIGGERS];
+        triggers[0].vcount = 0;
+        Pass { triggers, len: 2, tail: 1 }
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+        self.tail = 0;
+    }
+
+    /// after any others with the same vcount, so effects registered first go first
+    fn insert(&mut self, vcount: u16, effect: u8) -> bool {
+        if self.len >= MAX_TRIGGERS - 1 {
+            return false;
+        }
+        let pos = self.triggers[..self.len]
+            .iter()
+            .position(|t| t.vcount as u16 > vcount)
+            .unwrap_or(self.len);
+        self.triggers.copy_within(pos..self.len, pos + 1);
+        self.triggers[pos] = Trigger { vcount: vcount as u8, effect };
+        self.len += 1;
+        true
+    }
+}
+
+/// runs effects at particular scanlines, by way of the vcount interrupt (and timer1, to
+/// wait for hblank).  effects can be added and removed whenever; the interrupt sequence gets
+/// rebuilt from them at the next vblank.
+pub struct ScanlineScheduler {
+    /// what's been registered, as the game sees it
+    effects: [Option<Registered>; MAX_SCANLINE_EFFECTS],
+    /// what the current passes were built from, only touched during vblank
+    live: [Option<Registered>; MAX_SCANLINE_EFFECTS],
+    passes: [Pass; 2],
+    pass: usize,
+    index: usize,
+    /// whether frames take turns between the passes, or just use the first
+    alternating: bool,
+    dirty: bool,
+    editing: bool,
+}
+
+impl ScanlineScheduler {
+    pub const fn new() -> Self {
+        ScanlineScheduler {
+            effects: [None; MAX_SCANLINE_EFFECTS],
+            live: [None; MAX_SCANLINE_EFFECTS],
+            passes: [Pass::new(), Pass::new()],
+            pass: 0,
+            index: 0,
+            alternating: true,
+            dirty: false,
+            editing: false,
+        }
+    }
+
+    /// takes effect from the frame after next vblank.  `None` if they're all taken, or if
+    /// `line` isn't a visible scanline.
+    pub fn add(&mut self, effect: ScanlineEffect) -> Option<ScanlineEffectId> {
+        let effect = ScanlineEffect {
+            line: effect.line,
+            every: effect.every,
+            until: effect.until,
+            frames: effect.frames,
+            action: Action::Game(effect.action),
+        };
+        self.add_for(Client::Game, effect)
+    }
+
+    pub fn remove(&mut self, id: ScanlineEffectId) {
+        self.edit(|effects| {
+            if let Some(slot) = effects.get_mut(id.0 as usize) {
+                *slot = None;
+            }
+        });
+    }
+
+    pub(crate) fn add_for(
+        &mut self,
+        client: Client,
+        effect: ScanlineEffect<Action>,
+    ) -> Option<ScanlineEffectId> {
+        if effect.line >= VBLANK_SCANLINE {
+            warn!("scanline effect at line {} would never happen", effect.line);
+            return None;
+        }
+        let registered = Registered { client, effect };
+        let mut id = None;
+        self.edit(|effects| {
+            if let Some(index) = effects.iter().position(Option::is_none) {
+                effects[index] = Some(registered);
+                id = Some(ScanlineEffectId(index as u8));
+            }
+        });
+        if id.is_none() {
+            error!("out of scanline effects");
+        }
+        id
+    }
+
+    pub(crate) fn remove_client(&mut self, client: Client) {
+        self.edit(|effects| {
+            for slot in effects.iter_mut() {
+                if matches!(slot, Some(r) if r.client == client) {
+                    *slot = None;
+                }
+            }
+        });
+    }
+
+    /// with it off, `Even` & `Odd` effects go in every frame instead
+    pub(crate) fn set_alternating(&mut self, alternating: bool) {
+        self.alternating = alternating;
+        self.edit(|_| {});
+    }
+
+    /// keeps vblank from rebuilding off of a half-written effect
+    fn edit(&mut self, f: impl FnOnce(&mut [Option<Registered>; MAX_SCANLINE_EFFECTS])) {
+        self.editing = true;
+        compiler_fence(Ordering::SeqCst);
+        f(&mut self.effects);
+        self.dirty = true;
+        compiler_fence(Ordering::SeqCst);
+        self.editing = false;
+    }
+
+    /// only call during vblank
+    pub(crate) fn vblank(&mut self) {
+        if !self.dirty || self.editing {
+            return;
+        }
+        self.dirty = false;
+        self.live = self.effects;
+        let mut dropped = false;
+        let alternating = self.alternating;
+        let includes = |r: &Registered, odd: bool| !alternating || r.effect.frames.includes(odd);
+        for (odd, pass) in self.passes.iter_mut().enumerate() {
+            let odd = odd != 0;
+            pass.clear();
+            for (i, effect) in self.live.iter().enumerate() {
+                if let Some(Registered { effect, .. }) = effect.filter(|r| includes(r, odd)) {
+                    for line in effect.lines().filter(|l| *l > 0) {
+                        dropped |= !pass.insert(line - 1, i as u8);
+                    }
+                }
+            }
+            if pass.len == 0 {
+                // so the vcount setting always moves off of VCOUNT_FRAME_START in between
+                pass.insert(0, NO_EFFECT);
+            }
+            pass.tail = pass.len;
+            // scanline 0 belongs to the frame after this one
+            for (i, effect) in self.live.iter().enumerate() {
+                if effect.filter(|r| includes(r, !odd) && r.effect.line == 0).is_some() {
+                    dropped |= !pass.insert(VCOUNT_FRAME_START, i as u8);
+                }
+            }
+            pass.triggers[pass.len] = Trigger { vcount: VCOUNT_FRAME_START as u8, effect: NO_EFFECT };
+            pass.len += 1;
+        }
+        if dropped {
+            warn!("too many scanline effects, only using the first {} per frame", MAX_TRIGGERS);
+        }
+        // we're past everything but the end of this frame's pass
+        self.index = self.passes[self.pass].tail;
+    }
+
+    /// the scanline the triggers waiting on the current vcount are for, and which they are
+    #[link_section = ".iwram"]
+    pub(crate) fn due(&self) -> (u16, Range<usize>) {
+        let pass = &self.passes[self.pass];
+        let start = self.index;
+        let vcount = pass.triggers[start].vcount;
+        let end = start + pass.triggers[start..pass.len].iter().take_while(|t| t.vcount == vcount).count();
+        let line = if vcount as u16 == VCOUNT_FRAME_START { 0 } else { vcount as u16 + 1 };
+        (line, start..end)
+    }
+
+    #[link_section = ".iwram"]
+    pub(crate) fn action(&self, index: usize) -> Option<Action> {
+        let effect = self.passes[self.pass].triggers[index].effect;
+        self.live.get(effect as usize).copied().flatten().map(|r| r.effect.action)
+    }
+
+    /// moves past what `due` returned, and gives the vcount to interrupt on next.
+    /// returns `true` alongside it if that was the end of a frame.
+    #[link_section = ".iwram"]
+    pub(crate) fn advance(&mut self, done: Range<usize>, next_frame_odd: bool) -> (u16, bool) {
+        self.index = done.end;
+        let wrapped = self.index >= self.passes[self.pass].len;
+        if wrapped {
+            self.pass = next_frame_odd as usize;
+            self.index = 0;
+        }
+        (self.passes[self.pass].triggers[self.index].vcount as u16, wrapped)
+    }
+}
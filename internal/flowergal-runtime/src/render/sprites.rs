@@ -0,0 +1,179 @@
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use gba::io::dma::DMA3;
+use gba::oam::{OBJAttr0, OBJAttr1, OBJAttr2, ObjectAttributes, ObjectRender};
+
+pub const OBJ_COUNT: usize = 128;
+pub const AFFINE_COUNT: usize = 32;
+
+/// the OBJ-window mask behind the textbox and side borders takes the first few slots (so it's
+/// always processed first), along with most of charblock 4.
+pub const WINDOW_MASK_SLOTS: usize = 9;
+/// game sprites should put their tiles here and up, i.e. in charblock 5.
+pub const FIRST_FREE_OBJ_TILE: u16 = 512;
+
+const OAM_ADDR: usize = 0x0700_0000;
+const HIDDEN_ATTR0: u16 = 0x0200; // ObjectRender::Disabled
+
+/// handle to one of the 128 OBJ attribute entries
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct SpriteSlot(u8);
+
+/// handle to one of the 32 OBJ affine matrices, for `OBJAttr1::with_affine_index`
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct AffineSlot(u8);
+
+impl SpriteSlot {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl AffineSlot {
+    pub fn index(self) -> u16 {
+        self.0 as u16
+    }
+}
+
+/// 8.8 fixed point, as the hardware wants them
+#[derive(Copy, Clone, Debug)]
+pub struct AffineMatrix {
+    pub pa: i16,
+    pub pb: i16,
+    pub pc: i16,
+    pub pd: i16,
+}
+
+impl AffineMatrix {
+    pub const IDENTITY: AffineMatrix = AffineMatrix { pa: 0x100, pb: 0, pc: 0, pd: 0x100 };
+}
+
+/// OAM is laid out as 128 entries of four halfwords, with the affine matrices' parameters
+/// interleaved into every fourth halfword.  we mirror that exactly so the copy is one DMA.
+#[repr(C, align(4))]
+struct OamBuffer([[u16; 4]; OBJ_COUNT]);
+
+/// everything the game wants in OAM, copied over all at once during vblank so sprites never
+/// get drawn half-updated.
+pub struct ShadowOam {
+    entries: OamBuffer,
+    used_sprites: u128,
+    used_affines: u32,
+    dirty: bool,
+    editing: bool,
+}
+
+impl ShadowOam {
+    pub const fn new() -> Self {
+        ShadowOam {
+            entries: OamBuffer([[HIDDEN_ATTR0, 0, 0, 0]; OBJ_COUNT]),
+            used_sprites: (1 << WINDOW_MASK_SLOTS) - 1,
+            used_affines: 0,
+            dirty: true,
+            editing: false,
+        }
+    }
+
+    /// lowest free slot, which the hardware draws in front of any higher one.
+    /// starts out hidden.
+    pub fn alloc_sprite(&mut self) -> Option<SpriteSlot> {
+        let free = !self.used_sprites;
+        if free == 0 {
+            return None;
+        }
+        let index = free.trailing_zeros() as u8;
+        self.used_sprites |= 1 << index;
+        Some(SpriteSlot(index))
+    }
+
+    pub fn free_sprite(&mut self, slot: SpriteSlot) {
+        debug_assert!(slot.index() >= WINDOW_MASK_SLOTS);
+        self.hide_sprite(slot);
+        self.used_sprites &= !(1 << slot.0);
+    }
+
+    pub fn set_sprite(&mut self, slot: SpriteSlot, attrs: ObjectAttributes) {
+        self.edit(|entries| {
+            let entry = &mut entries.0[slot.index()];
+            entry[0] = attrs.attr0.0;
+            entry[1] = attrs.attr1.0;
+            entry[2] = attrs.attr2.0;
+        });
+    }
+
+    pub fn sprite(&self, slot: SpriteSlot) -> ObjectAttributes {
+        let entry = &self.entries.0[slot.index()];
+        ObjectAttributes {
+            attr0: OBJAttr0(entry[0]),
+            attr1: OBJAttr1(entry[1]),
+            attr2: OBJAttr2(entry[2]),
+        }
+    }
+
+    pub fn hide_sprite(&mut self, slot: SpriteSlot) {
+        self.edit(|entries| {
+            let entry = &mut entries.0[slot.index()];
+            entry[0] = OBJAttr0(entry[0]).with_obj_rendering(ObjectRender::Disabled).0;
+        });
+    }
+
+    pub fn alloc_affine(&mut self) -> Option<AffineSlot> {
+        let free = !self.used_affines;
+        if free == 0 {
+            return None;
+        }
+        let index = free.trailing_zeros() as u8;
+        self.used_affines |= 1 << index;
+        let slot = AffineSlot(index);
+        self.set_affine(slot, AffineMatrix::IDENTITY);
+        Some(slot)
+    }
+
+    /// any sprites still pointing at this matrix keep using whatever's put in it next
+    pub fn free_affine(&mut self, slot: AffineSlot) {
+        self.used_affines &= !(1 << slot.0);
+    }
+
+    pub fn set_affine(&mut self, slot: AffineSlot, matrix: AffineMatrix) {
+        let base = slot.0 as usize * 4;
+        self.edit(|entries| {
+            entries.0[base][3] = matrix.pa as u16;
+            entries.0[base + 1][3] = matrix.pb as u16;
+            entries.0[base + 2][3] = matrix.pc as u16;
+            entries.0[base + 3][3] = matrix.pd as u16;
+        });
+    }
+
+    pub(crate) fn set_window_mask_sprite(&mut self, index: usize, attrs: ObjectAttributes) {
+        debug_assert!(index < WINDOW_MASK_SLOTS);
+        self.set_sprite(SpriteSlot(index as u8), attrs);
+    }
+
+    /// keeps vblank from committing a half-written entry.  (vblank edits the window mask
+    /// itself, possibly in the middle of one of the game's edits, hence putting `editing`
+    /// back the way it was rather than clearing it)
+    fn edit(&mut self, f: impl FnOnce(&mut OamBuffer)) {
+        let was_editing = self.editing;
+        self.editing = true;
+        compiler_fence(Ordering::SeqCst);
+        f(&mut self.entries);
+        self.dirty = true;
+        compiler_fence(Ordering::SeqCst);
+        self.editing = was_editing;
+    }
+
+    /// only call during vblank
+    pub(crate) fn commit(&mut self) {
+        if !self.dirty || self.editing {
+            return;
+        }
+        self.dirty = false;
+        let words = unsafe {
+            core::slice::from_raw_parts(
+                self.entries.0.as_ptr() as *const u32,
+                core::mem::size_of::<OamBuffer>() / 4,
+            )
+        };
+        unsafe { DMA3::copy_slice_to_address(words, OAM_ADDR) };
+    }
+}
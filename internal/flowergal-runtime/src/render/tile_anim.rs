@@ -0,0 +1,74 @@
+use core::mem::size_of;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use gba::io::dma::DMA3;
+use gba::vram::{Tile4bpp, CHAR_BASE_BLOCKS};
+
+use flowergal_proj_config::resources::AnimTile;
+
+use crate::memory::{CoreLib, MemoryOps};
+
+/// swaps the graphics of a world's animated tiles in the charblock, so every map entry
+/// pointing at one of them animates without the maps being touched.
+pub struct TileAnimator {
+    charblock_addr: usize,
+    anims: &'static [AnimTile],
+    frame: u32,
+    /// keeps vblank away from a half-swapped table
+    editing: bool,
+}
+
+impl TileAnimator {
+    pub const fn new() -> Self {
+        TileAnimator {
+            charblock_addr: 0,
+            anims: &[],
+            frame: 0,
+            editing: false,
+        }
+    }
+
+    /// `anims`' tile numbers are relative to the start of `charblock`
+    pub fn load(&mut self, charblock: u16, anims: &'static [AnimTile]) {
+        assert!(charblock < 4);
+        self.editing = true;
+        compiler_fence(Ordering::SeqCst);
+        self.charblock_addr = CHAR_BASE_BLOCKS.index(charblock as usize).to_usize();
+        self.anims = anims;
+        self.frame = 0;
+        // so they all start on their first frame instead of whatever the tileset had.
+        // (by CPU, since vblank programs DMA3 and could land in the middle of us doing so)
+        for anim in self.anims {
+            if let Some(tile) = anim.frames.first() {
+                unsafe { CoreLib::copy_slice_to_address(&tile.0, self.tile_addr(anim)) };
+            }
+        }
+        compiler_fence(Ordering::SeqCst);
+        self.editing = false;
+    }
+
+    /// only call during vblank
+    pub(crate) fn vblank(&mut self) {
+        if self.editing {
+            return;
+        }
+        self.frame = self.frame.wrapping_add(1);
+        for anim in self.anims {
+            let frames_per_step = anim.frames_per_step.max(1) as u32;
+            if self.frame % frames_per_step == 0 && !anim.frames.is_empty() {
+                let step = (self.frame / frames_per_step) as usize % anim.frames.len();
+                self.show(anim, step);
+            }
+        }
+    }
+
+    fn show(&self, anim: &AnimTile, step: usize) {
+        if let Some(tile) = anim.frames.get(step) {
+            unsafe { DMA3::copy_slice_to_address(&tile.0, self.tile_addr(anim)) };
+        }
+    }
+
+    fn tile_addr(&self, anim: &AnimTile) -> usize {
+        self.charblock_addr + anim.tile_num as usize * size_of::<Tile4bpp>()
+    }
+}
@@ -0,0 +1,182 @@
+use gba::io::color_blend::{BrightnessSetting, ColorEffectSetting, ColorSpecialEffect, BLDCNT, BLDY};
+use gba::io::display::{MosaicSetting, MOSAIC};
+
+use crate::render::window::{WindowShape, Windows};
+use crate::render::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const WIDTH: u32 = SCREEN_WIDTH as u32;
+const HEIGHT: u32 = SCREEN_HEIGHT as u32;
+const MOSAIC_MAX: u32 = 15;
+const BLDY_MAX: u32 = 16;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TransitionEffect {
+    Mosaic,
+    FadeToBlack,
+    FadeToWhite,
+    /// the edge of the curtain moves in this direction, both going out and coming back in
+    Wipe(WipeDirection),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum WipeDirection {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TransitionPhase {
+    /// the world goes away, and stays gone until an `In` starts
+    Out,
+    /// the world comes back, and then everything's put back the way it was
+    In,
+}
+
+enum State {
+    Idle,
+    Running { phase: TransitionPhase, frame: u16 },
+    Hidden,
+}
+
+/// screen transitions applied to the world layers (the HUD is left alone), stepped along
+/// once a frame during vblank so nothing has to sit and wait for them.
+pub struct Transition {
+    effect: TransitionEffect,
+    frames: u16,
+    state: State,
+    /// whatever blending the world had going before (or during) a fade
+    restore_bldcnt: ColorEffectSetting,
+}
+
+impl Transition {
+    pub const fn new() -> Self {
+        Transition {
+            effect: TransitionEffect::FadeToBlack,
+            frames: 1,
+            state: State::Idle,
+            restore_bldcnt: ColorEffectSetting::new(),
+        }
+    }
+
+    pub fn start(&mut self, windows: &mut Windows, effect: TransitionEffect, phase: TransitionPhase, frames: u16) {
+        if effect != self.effect && !matches!(self.state, State::Idle) {
+            self.clear(windows);
+        }
+        self.effect = effect;
+        self.frames = frames.max(1);
+        self.state = State::Running { phase, frame: 0 };
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(self.state, State::Running { .. })
+    }
+
+    /// an `Out` has finished, so it's safe to rearrange the world without anyone seeing
+    pub fn is_hidden(&self) -> bool {
+        matches!(self.state, State::Hidden)
+    }
+
+    /// only call during vblank, before `windows` is committed
+    pub(crate) fn vblank(&mut self, windows: &mut Windows) {
+        match self.state {
+            State::Idle => {}
+            // keep at it, in case loading a world stomped on our registers
+            State::Hidden => self.apply(windows, TransitionPhase::Out, self.frames),
+            State::Running { phase, frame } => {
+                let frame = frame + 1;
+                self.apply(windows, phase, frame);
+                self.state = if frame < self.frames {
+                    State::Running { phase, frame }
+                } else if phase == TransitionPhase::Out {
+                    State::Hidden
+                } else {
+                    self.clear(windows);
+                    State::Idle
+                };
+            }
+        }
+    }
+
+    /// `progress` is how many of `frames` have gone by in this phase
+    fn apply(&mut self, windows: &mut Windows, phase: TransitionPhase, progress: u16) {
+        let progress = progress.min(self.frames) as u32;
+        let frames = self.frames as u32;
+        let hidden = match phase {
+            TransitionPhase::Out => progress,
+            TransitionPhase::In => frames - progress,
+        };
+        match self.effect {
+            TransitionEffect::Mosaic => {
+                let size = (hidden * MOSAIC_MAX / frames) as u16;
+                MOSAIC.write(
+                    MosaicSetting::new()
+                        .with_bg_horizontal_inc(size)
+                        .with_bg_vertical_inc(size)
+                        .with_obj_horizontal_inc(size)
+                        .with_obj_vertical_inc(size),
+                );
+            }
+            TransitionEffect::FadeToBlack | TransitionEffect::FadeToWhite => {
+                let current = BLDCNT.read();
+                match current.color_special_effect() {
+                    ColorSpecialEffect::BrightnessIncrease | ColorSpecialEffect::BrightnessDecrease => {}
+                    _ => self.restore_bldcnt = current,
+                }
+                let effect = if self.effect == TransitionEffect::FadeToBlack {
+                    ColorSpecialEffect::BrightnessDecrease
+                } else {
+                    ColorSpecialEffect::BrightnessIncrease
+                };
+                BLDCNT.write(
+                    ColorEffectSetting::new()
+                        .with_bg1_1st_target_pixel(true)
+                        .with_bg2_1st_target_pixel(true)
+                        .with_bg3_1st_target_pixel(true)
+                        .with_obj_1st_target_pixel(true)
+                        .with_backdrop_1st_target_pixel(true)
+                        .with_color_special_effect(effect),
+                );
+                BLDY.write(BrightnessSetting::new().with_evy_coefficient(hidden * BLDY_MAX / frames));
+            }
+            TransitionEffect::Wipe(direction) => {
+                let edge = |len: u32| progress * len / frames;
+                let out = phase == TransitionPhase::Out;
+                let (x, y) = match direction {
+                    WipeDirection::Right if out => ((0, edge(WIDTH)), (0, HEIGHT)),
+                    WipeDirection::Right => ((edge(WIDTH), WIDTH), (0, HEIGHT)),
+                    WipeDirection::Left if out => {
+                        ((WIDTH - edge(WIDTH), WIDTH), (0, HEIGHT))
+                    }
+                    WipeDirection::Left => ((0, WIDTH - edge(WIDTH)), (0, HEIGHT)),
+                    WipeDirection::Down if out => ((0, WIDTH), (0, edge(HEIGHT))),
+                    WipeDirection::Down => ((0, WIDTH), (edge(HEIGHT), HEIGHT)),
+                    WipeDirection::Up if out => {
+                        ((0, WIDTH), (HEIGHT - edge(HEIGHT), HEIGHT))
+                    }
+                    WipeDirection::Up => ((0, WIDTH), (0, HEIGHT - edge(HEIGHT))),
+                };
+                let covering = x.0 < x.1 && y.0 < y.1;
+                windows.borrow_win1(if covering {
+                    Some(WindowShape::Rect { left: x.0 as u8, top: y.0 as u8, right: x.1 as u8, bottom: y.1 as u8 })
+                } else {
+                    None
+                });
+            }
+        }
+    }
+
+    /// put back anything the current effect touched
+    fn clear(&mut self, windows: &mut Windows) {
+        match self.effect {
+            TransitionEffect::Mosaic => MOSAIC.write(MosaicSetting::new()),
+            TransitionEffect::FadeToBlack | TransitionEffect::FadeToWhite => {
+                BLDY.write(BrightnessSetting::new());
+                BLDCNT.write(self.restore_bldcnt);
+            }
+            TransitionEffect::Wipe(_) => windows.return_win1(),
+        }
+        self.state = State::Idle;
+    }
+}
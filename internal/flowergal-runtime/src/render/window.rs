@@ -0,0 +1,207 @@
+use gba::io::display::DISPCNT;
+use gba::io::window::{
+    HorizontalWindowSetting, InsideWindowSetting, OutsideWindowSetting, VerticalWindowSetting, WIN0H, WIN0V,
+    WIN1H, WIN1V, WININ, WINOUT,
+};
+
+use crate::render::hblank_dma::HblankDma;
+use crate::render::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Window {
+    Win0,
+    Win1,
+}
+
+/// what gets drawn in a region of the screen
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct WindowLayers {
+    pub bg0: bool,
+    pub bg1: bool,
+    pub bg2: bool,
+    pub bg3: bool,
+    pub obj: bool,
+    /// whether BLDCNT's effect applies
+    pub blend: bool,
+}
+
+impl WindowLayers {
+    pub const ALL: WindowLayers = WindowLayers { bg0: true, bg1: true, bg2: true, bg3: true, obj: true, blend: true };
+    pub const NONE: WindowLayers = WindowLayers { bg0: false, bg1: false, bg2: false, bg3: false, obj: false, blend: false };
+    /// just the HUD (BG0), e.g. for letterboxing or the outside of a spotlight
+    pub const HUD: WindowLayers = WindowLayers { bg0: true, ..WindowLayers::NONE };
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum WindowShape {
+    /// left & top inclusive, right & bottom exclusive
+    Rect { left: u8, top: u8, right: u8, bottom: u8 },
+    /// only spans from `top` to `bottom`, with the left & right of every scanline coming
+    /// from `HblankTarget::WindowH` (see `circle_spans`)
+    Scanlines { top: u8, bottom: u8 },
+}
+
+/// WIN0 & WIN1 and what's drawn inside and outside of them.  changes show up at the next
+/// vblank.  (a wipe transition borrows WIN1 while it runs, and the game's own WIN1 comes
+/// back when it's done)
+pub struct Windows {
+    shapes: [Option<WindowShape>; 2],
+    inside: [WindowLayers; 2],
+    outside: WindowLayers,
+    obj_window: WindowLayers,
+    /// the wipe's curtain, which only the HUD is drawn inside of.  `Some(None)` while it
+    /// isn't covering anything yet.
+    borrowed_win1: Option<Option<WindowShape>>,
+}
+
+impl Windows {
+    pub const fn new() -> Self {
+        Windows {
+            shapes: [None; 2],
+            inside: [WindowLayers::ALL; 2],
+            outside: WindowLayers::ALL,
+            // the skybox doesn't show through the mesh, so it's not drawn over twice
+            obj_window: WindowLayers { bg2: false, ..WindowLayers::ALL },
+            borrowed_win1: None,
+        }
+    }
+
+    pub fn set(&mut self, window: Window, shape: WindowShape, inside: WindowLayers) {
+        self.shapes[window as usize] = Some(shape);
+        self.inside[window as usize] = inside;
+    }
+
+    pub fn disable(&mut self, window: Window) {
+        self.shapes[window as usize] = None;
+    }
+
+    pub fn shape(&self, window: Window) -> Option<WindowShape> {
+        self.shapes[window as usize]
+    }
+
+    /// everywhere not covered by a window (or the OBJ window)
+    pub fn set_outside(&mut self, layers: WindowLayers) {
+        self.outside = layers;
+    }
+
+    /// inside the OBJ window, i.e. the flicker mesh behind the textbox and borders
+    pub fn set_obj_window(&mut self, layers: WindowLayers) {
+        self.obj_window = layers;
+    }
+
+    /// take over WIN1 for a transition, leaving whatever the game set there to be put back
+    /// by `return_win1`
+    pub(crate) fn borrow_win1(&mut self, shape: Option<WindowShape>) {
+        self.borrowed_win1 = Some(shape);
+    }
+
+    pub(crate) fn return_win1(&mut self) {
+        self.borrowed_win1 = None;
+    }
+
+    /// only call during vblank
+    pub(crate) fn commit(&self) {
+        let (shapes, inside) = match self.borrowed_win1 {
+            Some(shape) => ([self.shapes[0], shape], [self.inside[0], WindowLayers::HUD]),
+            None => (self.shapes, self.inside),
+        };
+        let regs = [(WIN0H, WIN0V), (WIN1H, WIN1V)];
+        for (shape, (winh, winv)) in shapes.iter().zip(regs.iter()) {
+            let vertical = |top: u8, bottom: u8| {
+                VerticalWindowSetting::new().with_row_start(top as u16).with_row_end(bottom as u16)
+            };
+            match *shape {
+                Some(WindowShape::Rect { left, top, right, bottom }) => {
+                    winh.write(
+                        HorizontalWindowSetting::new()
+                            .with_col_start(left as u16)
+                            .with_col_end(right as u16),
+                    );
+                    winv.write(vertical(top, bottom));
+                }
+                // the hblank DMA takes care of WINxH
+                Some(WindowShape::Scanlines { top, bottom }) => winv.write(vertical(top, bottom)),
+                None => {}
+            }
+        }
+        let (win0, win1) = (inside[0], inside[1]);
+        WININ.write(
+            InsideWindowSetting::new()
+                .with_win0_bg0(win0.bg0)
+                .with_win0_bg1(win0.bg1)
+                .with_win0_bg2(win0.bg2)
+                .with_win0_bg3(win0.bg3)
+                .with_win0_obj(win0.obj)
+                .with_win0_color_special(win0.blend)
+                .with_win1_bg0(win1.bg0)
+                .with_win1_bg1(win1.bg1)
+                .with_win1_bg2(win1.bg2)
+                .with_win1_bg3(win1.bg3)
+                .with_win1_obj(win1.obj)
+                .with_win1_color_special(win1.blend),
+        );
+        let (outside, obj_win) = (self.outside, self.obj_window);
+        WINOUT.write(
+            OutsideWindowSetting::new()
+                .with_outside_bg0(outside.bg0)
+                .with_outside_bg1(outside.bg1)
+                .with_outside_bg2(outside.bg2)
+                .with_outside_bg3(outside.bg3)
+                .with_outside_obj(outside.obj)
+                .with_outside_color_special(outside.blend)
+                .with_obj_win_bg0(obj_win.bg0)
+                .with_obj_win_bg1(obj_win.bg1)
+                .with_obj_win_bg2(obj_win.bg2)
+                .with_obj_win_bg3(obj_win.bg3)
+                .with_obj_win_obj(obj_win.obj)
+                .with_obj_win_color_special(obj_win.blend),
+        );
+        DISPCNT.write(
+            DISPCNT
+                .read()
+                .with_win0(shapes[0].is_some())
+                .with_win1(shapes[1].is_some()),
+        );
+    }
+}
+
+/// fills the back table of a `HblankTarget::WindowH` DMA with a circle, and returns the
+/// shape to give the window.  shrink or grow the radius every frame for an iris-in/out.
+pub fn circle_spans(dma: &mut HblankDma, center_x: i32, center_y: i32, radius: i32) -> WindowShape {
+    let radius = radius.max(0);
+    for line in 0..SCREEN_HEIGHT {
+        let dy = line - center_y;
+        let (left, right) = if dy.abs() < radius {
+            let half = isqrt((radius * radius - dy * dy) as u32) as i32;
+            (
+                (center_x - half).max(0).min(SCREEN_WIDTH),
+                (center_x + half).max(0).min(SCREEN_WIDTH),
+            )
+        } else {
+            (0, 0)
+        };
+        dma.set_span(line as usize, left as u8, right as u8);
+    }
+    WindowShape::Scanlines {
+        top: (center_y - radius).max(0).min(SCREEN_HEIGHT) as u8,
+        bottom: (center_y + radius).max(0).min(SCREEN_HEIGHT) as u8,
+    }
+}
+
+fn isqrt(n: u32) -> u32 {
+    let mut bit = 1u32 << 30;
+    while bit > n {
+        bit >>= 2;
+    }
+    let (mut n, mut result) = (n, 0);
+    while bit != 0 {
+        if n >= result + bit {
+            n -= result + bit;
+            result = (result >> 1) + bit;
+        } else {
+            result >>= 1;
+        }
+        bit >>= 2;
+    }
+    result
+}
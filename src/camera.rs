@@ -0,0 +1,270 @@
+use core::mem::size_of;
+
+use flowergal_proj_config::resources::{
+    AlignWrapper, Layer, RoomData, RoomEntries4bpp, TextScreenblockEntry, ROOM_SIZE,
+};
+use flowergal_runtime::render::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use flowergal_runtime::{CoreLib, MemoryOps};
+
+/// tiles in a `BGSize::One` text background: two screenblocks side by side.
+/// the world gets wrapped around this like a ring buffer in both directions.
+const MAP_COLS: i32 = 64;
+const MAP_ROWS: i32 = 32;
+const SCREENBLOCK_COLS: usize = 32;
+const SCREENBLOCK_BYTES: usize = 0x800;
+
+// draw_row assumes a room's row never straddles two screenblocks
+const ROOM_COLS: i32 = ROOM_SIZE.0 as i32;
+const ROOM_ROWS: i32 = ROOM_SIZE.1 as i32;
+
+/// a column of the map can touch two rooms and a row three, for each of bg & fg.
+/// 2KiB apiece.
+const ROOM_CACHE_SLOTS: usize = 8;
+
+type RoomBuffer = [[u16; ROOM_SIZE.0]; ROOM_SIZE.1];
+
+#[link_section = ".ewram"]
+static mut ROOM_CACHE: AlignWrapper<[RoomBuffer; ROOM_CACHE_SLOTS]> =
+    AlignWrapper([[[0; ROOM_SIZE.0]; ROOM_SIZE.1]; ROOM_CACHE_SLOTS]);
+
+/// bookkeeping for which compressed rooms are sitting decompressed in `ROOM_CACHE`.
+/// (there's only the one World, so only the one of these)
+struct RoomCache {
+    /// address of the LZ77 data each slot was decompressed from, 0 if empty
+    sources: [usize; ROOM_CACHE_SLOTS],
+    last_used: [u32; ROOM_CACHE_SLOTS],
+    clock: u32,
+}
+
+impl RoomCache {
+    const fn new() -> Self {
+        RoomCache {
+            sources: [0; ROOM_CACHE_SLOTS],
+            last_used: [0; ROOM_CACHE_SLOTS],
+            clock: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.sources = [0; ROOM_CACHE_SLOTS];
+        self.last_used = [0; ROOM_CACHE_SLOTS];
+    }
+
+    fn get(&mut self, src: &'static [u32]) -> &'static RoomEntries4bpp {
+        self.clock = self.clock.wrapping_add(1);
+        let key = src.as_ptr() as usize;
+        let slot = match self.sources.iter().position(|s| *s == key) {
+            Some(slot) => slot,
+            None => {
+                // least recently used (or never used) gets evicted
+                let slot = (0..ROOM_CACHE_SLOTS)
+                    .min_by_key(|i| self.last_used[*i])
+                    .unwrap_or_default();
+                unsafe {
+                    let dest = ROOM_CACHE.0[slot].as_mut_ptr() as *mut u16;
+                    gba::bios::lz77_uncomp_16bit(src.as_ptr(), dest);
+                }
+                self.sources[slot] = key;
+                slot
+            }
+        };
+        self.last_used[slot] = self.clock;
+        unsafe { &*(ROOM_CACHE.0[slot].as_ptr() as *const RoomEntries4bpp) }
+    }
+}
+
+/// a view into the world with pixel precision.  rather than redrawing whole rooms when it
+/// moves, it keeps a 64x32-tile window of the world around itself in the screenblocks
+/// and only draws the columns & rows of tiles that scroll into that window.
+pub struct Camera {
+    x: i32,
+    y: i32,
+    /// world tile coords of the top-left of what's in the screenblocks, if anything
+    drawn: Option<(i32, i32)>,
+    cache: RoomCache,
+}
+
+impl Camera {
+    pub const fn new() -> Self {
+        Camera {
+            x: 0,
+            y: 0,
+            drawn: None,
+            cache: RoomCache::new(),
+        }
+    }
+
+    pub fn position(&self) -> (i32, i32) {
+        (self.x, self.y)
+    }
+
+    /// takes effect on the next `stream`
+    pub fn set_position(&mut self, x: i32, y: i32) {
+        self.x = x;
+        self.y = y;
+    }
+
+    /// what to put in BGxHOFS/BGxVOFS. the registers only look at the low 9 bits, which
+    /// lines up with how the screenblocks wrap.
+    pub fn scroll_offsets(&self) -> (u16, u16) {
+        (self.x as u16, self.y as u16)
+    }
+
+    /// forget what's in the screenblocks (and the room cache), e.g. after loading a world
+    pub fn invalidate(&mut self) {
+        self.drawn = None;
+        self.cache.clear();
+    }
+
+    /// draws whatever tiles have come into range since the last call. `layers` pairs each
+    /// layer with the address of its (`BGSize::One`) screenblocks, and should be the same
+    /// every call until the next `invalidate`.
+    pub fn stream(&mut self, layers: &[(Option<&'static Layer>, usize)]) {
+        let left = self.x.div_euclid(8);
+        let top = self.y.div_euclid(8);
+        let right = (self.x + SCREEN_WIDTH - 1).div_euclid(8);
+        let bottom = (self.y + SCREEN_HEIGHT - 1).div_euclid(8);
+
+        let (drawn_left, drawn_top) = match self.drawn {
+            Some(drawn) => drawn,
+            None => {
+                self.redraw(layers, left, top);
+                return;
+            }
+        };
+
+        let new_left = if left < drawn_left {
+            left
+        } else if right >= drawn_left + MAP_COLS {
+            right - MAP_COLS + 1
+        } else {
+            drawn_left
+        };
+        let new_top = if top < drawn_top {
+            top
+        } else if bottom >= drawn_top + MAP_ROWS {
+            bottom - MAP_ROWS + 1
+        } else {
+            drawn_top
+        };
+
+        if (new_left - drawn_left).abs() >= MAP_COLS || (new_top - drawn_top).abs() >= MAP_ROWS {
+            // nothing in there is any use to us anymore
+            self.redraw(layers, left, top);
+            return;
+        }
+
+        let new_cols = if new_left < drawn_left {
+            new_left..drawn_left
+        } else {
+            drawn_left + MAP_COLS..new_left + MAP_COLS
+        };
+        for tx in new_cols {
+            for &(layer, sb_addr) in layers {
+                if let Some(layer) = layer {
+                    self.draw_column(layer, sb_addr, tx, drawn_top);
+                }
+            }
+        }
+
+        // (the columns above only went as far as the old rows, so these go all the way across)
+        let new_rows = if new_top < drawn_top {
+            new_top..drawn_top
+        } else {
+            drawn_top + MAP_ROWS..new_top + MAP_ROWS
+        };
+        for ty in new_rows {
+            for &(layer, sb_addr) in layers {
+                if let Some(layer) = layer {
+                    self.draw_row(layer, sb_addr, ty, new_left);
+                }
+            }
+        }
+
+        self.drawn = Some((new_left, new_top));
+    }
+
+    fn redraw(&mut self, layers: &[(Option<&'static Layer>, usize)], left: i32, top: i32) {
+        for ty in top..top + MAP_ROWS {
+            for &(layer, sb_addr) in layers {
+                if let Some(layer) = layer {
+                    self.draw_row(layer, sb_addr, ty, left);
+                }
+            }
+        }
+        self.drawn = Some((left, top));
+    }
+
+    /// `None` for anywhere outside the world
+    fn room(&mut self, layer: &'static Layer, room_row: i32, room_col: i32) -> Option<&'static RoomEntries4bpp> {
+        if room_row < 0 || room_col < 0 {
+            return None;
+        }
+        let room_id = *layer.meta.0.get(room_row as usize)?.get(room_col as usize)? as usize;
+        match &layer.map {
+            RoomData::Text(rooms) | RoomData::Text8bpp(rooms) => {
+                let rooms: &'static [RoomEntries4bpp] = rooms;
+                rooms.get(room_id)
+            }
+            RoomData::TextLz77(rooms) | RoomData::Text8bppLz77(rooms) => {
+                let src = *rooms.get(room_id)?;
+                Some(self.cache.get(src))
+            }
+            RoomData::Affine(_) | RoomData::AffineLz77(_) => {
+                error!("Tried to scroll an affine layer as text");
+                None
+            }
+        }
+    }
+
+    fn draw_column(&mut self, layer: &'static Layer, sb_addr: usize, tx: i32, top: i32) {
+        let hx = tx.rem_euclid(MAP_COLS) as usize;
+        let room_col = tx.div_euclid(ROOM_COLS);
+        let col_in_room = tx.rem_euclid(ROOM_COLS) as usize;
+        let mut ty = top;
+        while ty < top + MAP_ROWS {
+            let room_row = ty.div_euclid(ROOM_ROWS);
+            let span_end = ((room_row + 1) * ROOM_ROWS).min(top + MAP_ROWS);
+            let room = self.room(layer, room_row, room_col);
+            for y in ty..span_end {
+                let entry = room
+                    .map(|r| r.0[y.rem_euclid(ROOM_ROWS) as usize][col_in_room])
+                    .unwrap_or_default();
+                let hy = y.rem_euclid(MAP_ROWS) as usize;
+                unsafe {
+                    (map_entry_addr(sb_addr, hx, hy) as *mut TextScreenblockEntry).write_volatile(entry);
+                }
+            }
+            ty = span_end;
+        }
+    }
+
+    fn draw_row(&mut self, layer: &'static Layer, sb_addr: usize, ty: i32, left: i32) {
+        let hy = ty.rem_euclid(MAP_ROWS) as usize;
+        let room_row = ty.div_euclid(ROOM_ROWS);
+        let row_in_room = ty.rem_euclid(ROOM_ROWS) as usize;
+        let blank = [TextScreenblockEntry::default(); ROOM_SIZE.0];
+        let mut tx = left;
+        while tx < left + MAP_COLS {
+            let room_col = tx.div_euclid(ROOM_COLS);
+            let span_end = ((room_col + 1) * ROOM_COLS).min(left + MAP_COLS);
+            let start = tx.rem_euclid(ROOM_COLS) as usize;
+            let end = start + (span_end - tx) as usize;
+            let src = match self.room(layer, room_row, room_col) {
+                Some(room) => &room.0[row_in_room][start..end],
+                None => &blank[start..end],
+            };
+            let hx = tx.rem_euclid(MAP_COLS) as usize;
+            unsafe {
+                CoreLib::copy_slice_to_address(src, map_entry_addr(sb_addr, hx, hy));
+            }
+            tx = span_end;
+        }
+    }
+}
+
+fn map_entry_addr(sb_addr: usize, hx: usize, hy: usize) -> usize {
+    sb_addr
+        + (hx / SCREENBLOCK_COLS) * SCREENBLOCK_BYTES
+        + (hy * SCREENBLOCK_COLS + hx % SCREENBLOCK_COLS) * size_of::<TextScreenblockEntry>()
+}
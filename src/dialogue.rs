@@ -0,0 +1,187 @@
+use gba::io::keypad::KeyInput;
+
+use heapless::consts::U256;
+
+use crate::hud::{self, Button, Hud, PROMPT_WIDTH, TEXT_ROWS, TEXT_WIDTH};
+
+/// forces a new page in a script
+pub const PAGE_BREAK: char = '\x0c';
+
+/// frames per character revealed
+const TYPE_SPEED: u32 = 2;
+
+type PageText = heapless::String<U256>;
+
+enum State {
+    Typing,
+    /// whole page shown, waiting on A
+    Prompting,
+    Finished,
+}
+
+/// a script typed out into the textbox a page at a time.
+/// B shows the rest of the page immediately; A moves on to the next one.
+pub struct Dialogue {
+    script: &'static str,
+    /// byte offset in `script` of the page after this one
+    next_page: usize,
+    page: PageText,
+    page_chars: usize,
+    shown_chars: usize,
+    state: State,
+    frame: u32,
+}
+
+impl Dialogue {
+    pub fn new(script: &'static str) -> Self {
+        let mut d = Dialogue {
+            script,
+            next_page: 0,
+            page: PageText::new(),
+            page_chars: 0,
+            shown_chars: 0,
+            state: State::Typing,
+            frame: 0,
+        };
+        d.next_page();
+        d
+    }
+
+    pub fn finished(&self) -> bool {
+        matches!(self.state, State::Finished)
+    }
+
+    pub fn handle_input(&mut self, new_keys: KeyInput) {
+        match self.state {
+            State::Typing if new_keys.b() => {
+                self.shown_chars = self.page_chars;
+                self.state = State::Prompting;
+            }
+            State::Prompting if new_keys.a() => self.next_page(),
+            _ => {}
+        }
+    }
+
+    pub fn advance_frame(&mut self) {
+        self.frame += 1;
+        if let State::Typing = self.state {
+            if self.frame % TYPE_SPEED == 0 {
+                self.shown_chars += 1;
+            }
+            if self.shown_chars >= self.page_chars {
+                self.shown_chars = self.page_chars;
+                self.state = State::Prompting;
+            }
+        }
+    }
+
+    pub fn draw(&self, h: &Hud) {
+        if self.finished() {
+            return;
+        }
+        let end = self
+            .page
+            .char_indices()
+            .nth(self.shown_chars)
+            .map_or(self.page.len(), |(i, _)| i);
+        h.clear_text_area();
+        h.draw_text(&self.page[..end]);
+        if let State::Prompting = self.state {
+            h.draw_prompt(Button::A, self.frame);
+        }
+    }
+
+    fn next_page(&mut self) {
+        let rest = &self.script[self.next_page..];
+        if rest.is_empty() {
+            self.state = State::Finished;
+            return;
+        }
+        let mut consumed = layout_page(rest, &mut self.page);
+        if consumed == 0 {
+            // (layout_page splits words to fit, so shouldn't happen) don't get stuck forever
+            consumed = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+        self.next_page += consumed;
+        self.page_chars = self.page.chars().count();
+        self.shown_chars = 0;
+        self.state = State::Typing;
+    }
+}
+
+/// word-wraps as much of `text` as fits in the textbox into `page`, with newlines where the
+/// lines break.  returns how many bytes of `text` it used up.
+fn layout_page(text: &str, page: &mut PageText) -> usize {
+    // leave room for the prompt (simpler to do it for every line than just the last)
+    let max_width = TEXT_WIDTH - PROMPT_WIDTH;
+    page.clear();
+    let mut row = 0;
+    let mut line_width = 0;
+    let mut pos = 0;
+    let mut pending_space = false;
+
+    while pos < text.len() {
+        let rest = &text[pos..];
+        let c = rest.chars().next().unwrap();
+        if c == PAGE_BREAK {
+            return pos + c.len_utf8();
+        }
+        if c == '\n' || c == ' ' {
+            pos += 1;
+            if c == '\n' {
+                row += 1;
+                if row >= TEXT_ROWS {
+                    return pos;
+                }
+                let _ = page.push('\n');
+                line_width = 0;
+                pending_space = false;
+            } else {
+                pending_space = line_width > 0;
+            }
+            continue;
+        }
+
+        let word_len = rest
+            .find(|c| c == ' ' || c == '\n' || c == PAGE_BREAK)
+            .unwrap_or_else(|| rest.len());
+        let word = &rest[..word_len];
+        let space_width = if pending_space { hud::text_width(" ") } else { 0 };
+        let word_width = hud::text_width(word);
+        if line_width > 0 && line_width + space_width + word_width > max_width {
+            row += 1;
+            if row >= TEXT_ROWS {
+                return pos;
+            }
+            let _ = page.push('\n');
+            line_width = 0;
+        } else if pending_space {
+            let _ = page.push(' ');
+            line_width += space_width;
+        }
+        pending_space = false;
+        if line_width + word_width <= max_width && page.push_str(word).is_ok() {
+            line_width += word_width;
+            pos += word_len;
+            continue;
+        }
+        // longer than a whole line (or the rest of the buffer), so split it up wherever it
+        // runs out of room, counting the rows that takes
+        for (i, c) in word.char_indices() {
+            let c_width = hud::text_width(c.encode_utf8(&mut [0; 4]));
+            if line_width > 0 && line_width + c_width > max_width {
+                row += 1;
+                if row >= TEXT_ROWS || page.push('\n').is_err() {
+                    return pos + i;
+                }
+                line_width = 0;
+            }
+            if page.push(c).is_err() {
+                return pos + i;
+            }
+            line_width += c_width;
+        }
+        pos += word_len;
+    }
+    pos
+}
@@ -1,11 +1,11 @@
 use gba::io::background::BackgroundControlSetting;
 use gba::io::background::{BGSize, BG0CNT};
 use gba::vram::text::TextScreenblockEntry;
-use gba::vram::SCREEN_BASE_BLOCKS;
+use gba::vram::{CHAR_BASE_BLOCKS, SCREEN_BASE_BLOCKS};
 
 use voladdress::VolAddress;
 
-use flowergal_proj_assets::{UI_IMG, UI_PAL};
+use flowergal_proj_assets::{UI_IMG, UI_PAL, VWF_ADVANCES, VWF_COLORS, VWF_FIRST_CHAR, VWF_GLYPHS};
 use flowergal_runtime::Driver;
 use flowergal_proj_config::resources::{TEXT_TOP_ROW, TEXT_BOTTOM_ROW};
 
@@ -23,11 +23,37 @@ pub const HUD_PALETTE: u16 = 15;
 pub const HUD_LEFT_COL: isize = 4;
 pub const HUD_RIGHT_COL: isize = HUD_LEFT_COL + 21;
 
+const TEXT_COLS: usize = 20;
+pub const TEXT_ROWS: usize = (TEXT_BOTTOM_ROW - TEXT_TOP_ROW - 1) as usize;
+/// in pixels
+pub const TEXT_WIDTH: usize = TEXT_COLS * 8;
+/// `draw_prompt` takes over the last cell of the last row
+pub const PROMPT_WIDTH: usize = 8;
+/// every cell of the text area gets its own tile for the variable-width font to draw into,
+/// at the end of the HUD charblock (512 tiles; anything past that is screenblocks)
+const VWF_FIRST_TILE: u16 = 512 - (TEXT_COLS * TEXT_ROWS) as u16;
+
+/// one row of the text area's tiles, 4bpp
+type TextLine = [[u32; 8]; TEXT_COLS];
+
+#[derive(Copy, Clone)]
 pub enum Button {
     A,
     B,
 }
 
+fn glyph_index(c: char) -> usize {
+    match (c as usize).checked_sub(VWF_FIRST_CHAR) {
+        Some(index) if index < VWF_GLYPHS.len() => index,
+        _ => '?' as usize - VWF_FIRST_CHAR,
+    }
+}
+
+/// how many pixels `draw_text` would take to draw this on one line
+pub fn text_width(string: &str) -> usize {
+    string.chars().map(|c| VWF_ADVANCES[glyph_index(c)] as usize).sum()
+}
+
 pub struct Hud {}
 
 impl Hud {
@@ -35,7 +61,6 @@ impl Hud {
         let bg_settings = BackgroundControlSetting::new()
             .with_screen_base_block(HUD_SCREENBLOCK_ID)
             .with_char_base_block(HUD_CHARBLOCK_ID)
-            .with_mosaic(true)
             .with_bg_priority(0)
             .with_size(BGSize::Zero);
 
@@ -43,13 +68,14 @@ impl Hud {
 
         let renderer = unsafe { Driver::instance_mut() }.video();
         renderer.set_normal_colors_bg(240, &UI_PAL);
+        assert!(UI_IMG.len() < VWF_FIRST_TILE as usize);
         renderer.load_bg_tiles(HUD_CHARBLOCK_ID, &UI_IMG);
 
         Hud {}
     }
 
     fn odd_frame(&self) -> bool {
-        unsafe { Driver::instance_mut().video() }.frame_counter & 1 != 0
+        unsafe { Driver::instance_mut().video() }.even_odd_frame()
     }
 
     fn write_entry(&self, row: isize, col: isize, entry: TSE) {
@@ -112,24 +138,91 @@ impl Hud {
         }
     }
 
+    /// proportional text, wrapping at the edge of the textbox.  lines past the third get dropped.
     pub fn draw_text(&self, string: &str) {
-        let left = HUD_LEFT_COL + 1;
-        let right = left + 20;
-        let mut row = TEXT_TOP_ROW + 1;
-        let mut col = left;
+        let mut line: TextLine = [[0; 8]; TEXT_COLS];
+        let mut row = 0;
+        let mut pen = 0;
         for c in string.chars() {
+            if row >= TEXT_ROWS {
+                return;
+            }
             if c == '\n' {
+                self.flush_text_line(row, &mut line);
                 row += 1;
-                col = left;
-            } else {
-                if col >= right {
-                    row += 1;
-                    col = left;
+                pen = 0;
+                continue;
+            }
+            let glyph = glyph_index(c);
+            let advance = VWF_ADVANCES[glyph] as usize;
+            if pen + advance > TEXT_WIDTH {
+                self.flush_text_line(row, &mut line);
+                row += 1;
+                pen = 0;
+                if row >= TEXT_ROWS {
+                    return;
                 }
-                self.write_entry(row, col, TSE::from_tile_id(c as u16));
-                col += 1;
             }
+            Self::draw_glyph(&mut line, glyph, pen, 0);
+            pen += advance;
+        }
+        if row < TEXT_ROWS {
+            self.flush_text_line(row, &mut line);
+        }
+    }
+
+    /// "press this to continue", in the bottom right corner of the text area.
+    /// `frame` bobs it up and down; call after `draw_text`, which would draw over it.
+    pub fn draw_prompt(&self, button: Button, frame: u32) {
+        let c = match button {
+            Button::A => 'A',
+            Button::B => 'B',
+        };
+        let mut line: TextLine = [[0; 8]; TEXT_COLS];
+        Self::draw_glyph(&mut line, glyph_index(c), TEXT_WIDTH - PROMPT_WIDTH, (frame >> 4) as usize & 1);
+        let tile = VWF_FIRST_TILE as usize + TEXT_ROWS * TEXT_COLS - 1;
+        let charblock = CHAR_BASE_BLOCKS.index(HUD_CHARBLOCK_ID as usize).to_usize();
+        unsafe { (charblock as *mut [u32; 8]).add(tile).write(line[TEXT_COLS - 1]) };
+        let entry = TSE::from_tile_id(tile as u16).with_palbank(HUD_PALETTE);
+        let offset = (TEXT_BOTTOM_ROW - 1) * 32 + HUD_RIGHT_COL - 1;
+        unsafe { HUD_SCREENBLOCK.offset(offset) }.write(entry);
+    }
+
+    /// expands the glyph's 2bpp rows to 4bpp and ORs them in at pixel column `x`, shifted
+    /// down `dy` rows
+    fn draw_glyph(line: &mut TextLine, glyph: usize, x: usize, dy: usize) {
+        for (y, bits) in VWF_GLYPHS[glyph].iter().enumerate() {
+            let y = y + dy;
+            if y >= 8 {
+                break;
+            }
+            for dx in 0..8 {
+                let code = (bits >> (dx * 2)) & 3;
+                let px = x + dx;
+                if code == 0 || px >= TEXT_WIDTH {
+                    continue;
+                }
+                let shift = (px % 8) * 4;
+                let row = &mut line[px / 8][y];
+                *row = (*row & !(0xF << shift)) | ((VWF_COLORS[code as usize] as u32) << shift);
+            }
+        }
+    }
+
+    /// copies a finished row into its tiles, points the text area at them, and blanks `line`
+    fn flush_text_line(&self, row: usize, line: &mut TextLine) {
+        let first_tile = VWF_FIRST_TILE as usize + row * TEXT_COLS;
+        let charblock = CHAR_BASE_BLOCKS.index(HUD_CHARBLOCK_ID as usize).to_usize();
+        unsafe {
+            core::slice::from_raw_parts_mut((charblock as *mut [u32; 8]).add(first_tile), TEXT_COLS)
+                .copy_from_slice(line);
+        }
+        for col in 0..TEXT_COLS {
+            let entry = TSE::from_tile_id((first_tile + col) as u16).with_palbank(HUD_PALETTE);
+            let offset = (TEXT_TOP_ROW + 1 + row as isize) * 32 + HUD_LEFT_COL + 1 + col as isize;
+            unsafe { HUD_SCREENBLOCK.offset(offset) }.write(entry);
         }
+        *line = [[0; 8]; TEXT_COLS];
     }
 }
 
@@ -0,0 +1,90 @@
+use core::fmt::Write;
+
+use gba::io::keypad::KeyInput;
+
+use bstr::ByteSlice;
+use heapless::consts::U80;
+
+use flowergal_proj_assets::MUSIC_DATA;
+use flowergal_proj_config::sound_info::{CYCLES_PER_FRAME, MUSIC_TITLES, SAMPLE_RATE};
+use flowergal_runtime::Driver;
+
+use crate::hud::Hud;
+
+/// sound test: left/right picks a track, A plays it, B stops it.
+pub struct Jukebox {
+    selected: usize,
+    playing: bool,
+    /// how far the decoder had gotten into the current track as of the last frame
+    /// (loops included), kept around after it stops
+    samples_played: usize,
+}
+
+impl Jukebox {
+    pub fn new() -> Self {
+        Jukebox {
+            selected: 0,
+            playing: false,
+            samples_played: 0,
+        }
+    }
+
+    pub fn handle_input(&mut self, new_keys: KeyInput) {
+        let audio = unsafe { Driver::instance_mut() }.audio();
+        let count = MUSIC_DATA.len();
+        if count == 0 {
+            return;
+        }
+
+        if new_keys.right() || new_keys.down() {
+            self.selected = (self.selected + 1) % count;
+        }
+        if new_keys.left() || new_keys.up() {
+            self.selected = (self.selected + count - 1) % count;
+        }
+        if new_keys.a() {
+            // set_bgm won't restart the same song, so get rid of it first
+            audio.stop_bgm();
+            audio.set_bgm(&MUSIC_DATA[self.selected]);
+            self.playing = true;
+            self.samples_played = 0;
+        }
+        if new_keys.b() {
+            audio.stop_bgm();
+            self.playing = false;
+        }
+    }
+
+    pub fn advance_frame(&mut self) {
+        let audio = unsafe { Driver::instance_mut() }.audio();
+        if self.playing {
+            match audio.bgm_samples_elapsed() {
+                Some(samples) => self.samples_played = samples,
+                None => self.playing = false,
+            }
+        }
+    }
+
+    pub fn draw(&self, h: &Hud) {
+        let audio = unsafe { Driver::instance_mut() }.audio();
+        let mut buf: heapless::Vec<u8, U80> = heapless::Vec::new();
+        let title = MUSIC_TITLES.get(self.selected).copied().unwrap_or("(no music)");
+        let seconds = self.samples_played / SAMPLE_RATE as usize;
+        let dec = audio.ticks_decode * 100 / (CYCLES_PER_FRAME / 64);
+        let _ = write!(
+            buf,
+            "{:2}/{:2} {:.14}\n\
+            {} {:3}:{:02}\n\
+            CPU: {:2}% dec",
+            self.selected + 1,
+            MUSIC_DATA.len(),
+            title,
+            if self.playing { "Playing" } else { "Stopped" },
+            seconds / 60,
+            seconds % 60,
+            dec.min(99)
+        );
+        h.clear_text_area();
+        h.draw_text(unsafe { buf.to_str_unchecked() });
+    }
+}
@@ -11,7 +11,10 @@
 #[macro_use]
 extern crate flowergal_runtime;
 
+pub mod camera;
+pub mod dialogue;
 pub mod hud;
+pub mod jukebox;
 pub mod world;
 
 use core::fmt::Write;
@@ -23,10 +26,26 @@ use bstr::ByteSlice;
 use heapless::consts::U80;
 
 use flowergal_runtime::Driver;
+use flowergal_runtime::render::transition::{TransitionEffect, WipeDirection};
+use flowergal_runtime::render::RenderMode;
 use flowergal_proj_config::sound_info::{SAMPLE_RATE, CYCLES_PER_FRAME};
 
 static mut G_HUD: Option<hud::Hud> = None;
 
+const INTRO_SCRIPT: &str = "Welcome to the flowergal FLAC demo! \
+    The music is 9-bit FLAC, decoded in real time on the GBA's CPU.\x0c\
+    Press A to hide the textbox, the D-pad to look around, R to change rooms, \
+    START for the jukebox, and SELECT to send the licenses to the mGBA log.";
+
+/// R hops to the next room over with each of these in turn
+const ROOM_TRANSITIONS: &[TransitionEffect] = &[
+    TransitionEffect::FadeToBlack,
+    TransitionEffect::Mosaic,
+    TransitionEffect::Wipe(WipeDirection::Right),
+    TransitionEffect::FadeToWhite,
+];
+const ROOM_TRANSITION_FRAMES: u16 = 30;
+
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
     if let Some(mut mgba) = gba::mgba::MGBADebug::new() {
@@ -76,18 +95,57 @@ fn main() -> ! {
     let mut text_showing = true;
     driver.video().set_textbox_shown(text_showing);
 
+    let mut jukebox: Option<jukebox::Jukebox> = None;
+    let mut intro = Some(dialogue::Dialogue::new(INTRO_SCRIPT));
+    let mut room_col = 0;
+    let mut room_transition = 0;
+
     let mut prev_keys = KeyInput::new();
     loop {
         let cur_keys = gba::io::keypad::read_key_input();
         let new_keys = cur_keys.pressed_since(prev_keys);
 
-        if new_keys.a() {
-            text_showing = !text_showing;
-            driver.video().set_textbox_shown(text_showing);
+        if new_keys.start() && intro.is_none() {
+            if jukebox.take().is_some() {
+                w.play_music();
+            } else {
+                jukebox = Some(jukebox::Jukebox::new());
+                text_showing = true;
+                driver.video().set_textbox_shown(text_showing);
+            }
             h.clear_text_area();
         }
 
-        if text_showing {
+        if let Some(d) = intro.as_mut() {
+            d.handle_input(new_keys);
+            d.advance_frame();
+            if d.finished() {
+                intro = None;
+                h.clear_text_area();
+            } else {
+                d.draw(h);
+            }
+        } else if let Some(j) = jukebox.as_mut() {
+            j.handle_input(new_keys);
+            j.advance_frame();
+            j.draw(h);
+        } else {
+            if new_keys.a() {
+                text_showing = !text_showing;
+                driver.video().set_textbox_shown(text_showing);
+                h.clear_text_area();
+            }
+            if new_keys.r() && !w.changing() {
+                room_col = (room_col + 1) % w.dimensions().0.max(1);
+                w.change_room(0, room_col, ROOM_TRANSITIONS[room_transition], ROOM_TRANSITION_FRAMES);
+                room_transition = (room_transition + 1) % ROOM_TRANSITIONS.len();
+            }
+            let dx = cur_keys.right() as i32 - cur_keys.left() as i32;
+            let dy = cur_keys.down() as i32 - cur_keys.up() as i32;
+            w.scroll_camera(dx, dy);
+        }
+
+        if text_showing && jukebox.is_none() && intro.is_none() {
             let mut buf: heapless::Vec<u8, U80> = heapless::Vec::new();
             let dec = driver.audio().ticks_decode * 100 / (CYCLES_PER_FRAME / 64);
             let mix = driver.audio().ticks_unmix * 100 / (CYCLES_PER_FRAME / 64);
@@ -103,6 +161,15 @@ fn main() -> ! {
             h.draw_text(unsafe { buf.to_str_unchecked() });
         }
 
+        if new_keys.l() {
+            // for screens that flicker instead of blending alternate frames together
+            let renderer = driver.video();
+            renderer.set_render_mode(match renderer.render_mode() {
+                RenderMode::Interframe => RenderMode::Stable,
+                RenderMode::Stable => RenderMode::Interframe,
+            });
+        }
+
         if new_keys.select() {
             if let Some(mut mgba) = gba::mgba::MGBADebug::new() {
                 for s in flowergal_proj_assets::LICENSE_TEXT.lines() {
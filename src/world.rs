@@ -2,24 +2,29 @@ use core::mem::size_of;
 
 use gba::bios::BgAffineSetParams;
 use gba::io::background::{
-    BGSize, BackgroundControlSetting, BG1CNT, BG1HOFS, BG1VOFS, BG2CNT, BG2HOFS, BG2VOFS, BG3CNT,
-    BG3HOFS, BG3VOFS,
+    BGSize, BackgroundControlSetting, BG0HOFS, BG0VOFS, BG1CNT, BG1HOFS, BG1VOFS, BG2CNT, BG2HOFS,
+    BG2VOFS, BG3CNT, BG3HOFS, BG3VOFS,
 };
 use gba::io::display::{DisplayControlSetting, DisplayMode, DISPCNT};
 use gba::vram::SCREEN_BASE_BLOCKS;
 
 use voladdress::VolAddress;
 
-use flowergal_proj_config::resources::{Layer, RoomData, TextScreenblockEntry, TilePatterns, WorldData};
-use flowergal_proj_config::WorldId;
+use flowergal_proj_config::resources::{
+    Curve, Layer, RoomData, SkyboxAnimation, TextScreenblockEntry, TilePatterns, WorldData,
+};
 
 use flowergal_runtime::{Driver, MemoryOps, CoreLib};
+use flowergal_runtime::render::transition::{TransitionEffect, TransitionPhase};
+use flowergal_runtime::render::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use flowergal_runtime::render::window::WindowLayers;
 
 use flowergal_proj_assets::MUSIC_DATA;
+
+use crate::camera::Camera;
 use gba::io::color_blend::{
     AlphaBlendingSetting, ColorEffectSetting, ColorSpecialEffect, BLDALPHA, BLDCNT,
 };
-use gba::io::window::OutsideWindowSetting;
 
 const WORLD_CHARBLOCK_ID: u16 = 0;
 const WORLD_CHARBLOCK_SPECIAL_ID: u16 = 1;
@@ -73,11 +78,6 @@ enum ScreenblockCorner {
     BottomRight,
 }
 
-enum SkyboxBehavior {
-    None,
-    Windmill,
-}
-
 impl ScreenblockCorner {
     pub fn text_offset(&self) -> usize {
         let tile_offset_within = TEXT_SCREENBLOCK_TILES - ROOM_TILES;
@@ -115,12 +115,23 @@ impl From<(usize, usize)> for ScreenblockCorner {
     }
 }
 
+enum Destination {
+    Room(usize, usize),
+    World(&'static WorldData, usize, usize),
+}
+
+/// where we're headed once the screen's done transitioning out
+struct PendingChange {
+    destination: Destination,
+    effect: TransitionEffect,
+    frames: u16,
+}
+
 pub struct World {
     world_data: Option<&'static WorldData>,
     frame_count: i32,
-    current_room_x: usize,
-    current_room_y: usize,
-    skybox_anim: SkyboxBehavior,
+    camera: Camera,
+    pending: Option<PendingChange>,
 }
 
 impl World {
@@ -155,20 +166,18 @@ impl World {
         World {
             world_data: None,
             frame_count: 0,
-            current_room_x: 0,
-            current_room_y: 0,
-            skybox_anim: SkyboxBehavior::None,
+            camera: Camera::new(),
+            pending: None,
         }
     }
 
     pub fn load_world(&mut self, data: &'static WorldData) {
         self.world_data = Some(data);
+        self.camera.invalidate();
 
-        let driver = unsafe { Driver::instance_mut() };
+        self.play_music();
 
-        if let Some(song_id) = data.music.0.first() {
-            driver.audio().set_bgm(&MUSIC_DATA[*song_id as usize]);
-        }
+        let driver = unsafe { Driver::instance_mut() };
 
         let renderer = driver.video();
         renderer.load_world_palettes(&data.pal);
@@ -180,10 +189,10 @@ impl World {
             data.skybox_layer
                 .as_ref()
                 .map(|sb| {
-                    if let RoomData::Text(_) = sb.map {
-                        "text"
-                    } else {
+                    if let RoomData::Affine(_) | RoomData::AffineLz77(_) = sb.map {
                         "affine"
+                    } else {
+                        "text"
                     }
                 })
                 .unwrap_or("none"),
@@ -207,17 +216,25 @@ impl World {
         BG3HOFS.write(BG_HOFS_BASE);
         BG3VOFS.write(BG_VOFS_BASE);
 
+        // 8bpp main tiles are for text-mode layers all the same, just with the one palette
+        if let Some(layer) = &data.bg_layer {
+            BG1CNT.write(BG1CNT.read().with_is_8bpp(layer.map.is_8bpp()));
+        }
+        if let Some(layer) = &data.fg_layer {
+            BG3CNT.write(BG3CNT.read().with_is_8bpp(layer.map.is_8bpp()));
+        }
         match data.img {
             TilePatterns::Text(imgs) => {
                 renderer.load_bg_tiles(WORLD_CHARBLOCK_ID, &imgs[..512.min(imgs.len())]);
             }
-            TilePatterns::TextLz77(data) => {
-                renderer.load_bg_tiles_lz77(WORLD_CHARBLOCK_ID, data);
+            TilePatterns::Affine(imgs) => {
+                renderer.load_bg_tiles(WORLD_CHARBLOCK_ID, &imgs[..256.min(imgs.len())]);
             }
-            TilePatterns::AffineLz77(_) | TilePatterns::Affine(_) => {
-                panic!("Tried to load world with 8bpp main images, not supported");
+            TilePatterns::TextLz77(data) | TilePatterns::AffineLz77(data) => {
+                renderer.load_bg_tiles_lz77(WORLD_CHARBLOCK_ID, data);
             }
         }
+        renderer.load_anim_tiles(WORLD_CHARBLOCK_ID, data.anim_tiles.0);
 
         match data.img_special {
             TilePatterns::Text(imgs) => {
@@ -238,11 +255,10 @@ impl World {
             }
         }
 
-        let bg1_settings = BG1CNT.read();
-        let bg2_settings = BG2CNT.read();
-        if data.id == WorldId::TomsDiner {
-            BG1CNT.write(bg1_settings);
-            BG2CNT.write(bg2_settings.with_size(BGSize::Zero));
+        if data.skybox_anim.affine.is_some() {
+            BG2CNT.write(BG2CNT.read().with_size(BGSize::Zero));
+        }
+        if data.skybox_anim.alpha.is_some() {
             BLDCNT.write(
                 ColorEffectSetting::new()
                     .with_bg1_1st_target_pixel(true)
@@ -254,14 +270,19 @@ impl World {
                     .with_eva_coefficient(4)
                     .with_evb_coefficient(12),
             );
-
-
-            self.skybox_anim = SkyboxBehavior::Windmill;
         }
 
         self.draw_skybox();
     }
 
+    /// (re)start this world's song, e.g. coming back from the jukebox
+    pub fn play_music(&self) {
+        let song_id = self.world_data.and_then(|data| data.music.0.first());
+        if let Some(song_id) = song_id {
+            unsafe { Driver::instance_mut() }.audio().set_bgm(&MUSIC_DATA[*song_id as usize]);
+        }
+    }
+
     pub fn draw_skybox(&mut self) {
         let sb_addr = ScreenblockAddress::Skybox;
         sb_addr.blocks_as_mut_slice(2).fill(0u16);
@@ -310,7 +331,7 @@ impl World {
         if room_row < meta.0.len() && room_col < meta_width {
             let room_id = meta.0[room_row][room_col];
             match map {
-                RoomData::Text(map) => {
+                RoomData::Text(map) | RoomData::Text8bpp(map) => {
                     let src = &map[room_id as usize].0;
                     let sb_addr = sb_addr as usize + sb_corner.text_offset();
                     for (row, entries) in src.iter().enumerate().take(ROOM_TILES) {
@@ -327,58 +348,117 @@ impl World {
                         sb_slice[start..end].copy_from_slice(entries);
                     }
                 }
-                RoomData::TextLz77(map) => {
+                RoomData::TextLz77(map) | RoomData::Text8bppLz77(map) => {
                     let src = map[room_id as usize];
                     let sb_addr = sb_addr as usize + sb_corner.text_offset();
                     gba::bios::lz77_uncomp_16bit(src.as_ptr(), sb_addr as *mut u16);
                 }
+                RoomData::AffineLz77(map) => {
+                    let src = map[room_id as usize];
+                    gba::bios::lz77_uncomp_16bit(src.as_ptr(), sb_addr as usize as *mut u16);
+                }
             }
         }
     }
 
+    /// jumps the camera to the top-left of the given room and draws everything around it
     pub fn draw_room(&mut self, room_row: usize, room_col: usize) {
         debug!("drawing room: row {}, col {}", room_row, room_col);
-        // TODO: refactor (draw_current_room separate from a mut set_current_room)
-        self.current_room_x = room_col;
-        self.current_room_y = room_row;
-        if let Some(world_data) = self.world_data {
-            if let Some(layer) = &world_data.bg_layer {
-                self.draw_room_layer(
-                    ScreenblockAddress::Background,
-                    ScreenblockCorner::TopLeft,
-                    layer,
-                    room_row,
-                    room_col,
-                );
-                self.draw_room_layer(
-                    ScreenblockAddress::Background,
-                    ScreenblockCorner::TopRight,
-                    layer,
-                    room_row,
-                    room_col + 1,
-                );
-            }
-            if let Some(layer) = &world_data.fg_layer {
-                self.draw_room_layer(
-                    ScreenblockAddress::Foreground,
-                    ScreenblockCorner::TopLeft,
-                    layer,
-                    room_row,
-                    room_col,
-                );
+        self.camera.invalidate();
+        self.set_camera((room_col * ROOM_TILES * 8) as i32, (room_row * ROOM_TILES * 8) as i32);
+        self.update_camera();
+    }
+
+    /// transitions out, draws the given room, then transitions back in, `frames` each way
+    pub fn change_room(&mut self, room_row: usize, room_col: usize, effect: TransitionEffect, frames: u16) {
+        self.begin_change(Destination::Room(room_row, room_col), effect, frames);
+    }
+
+    /// like `change_room`, but loading a whole other world (and its music) in between
+    pub fn change_world(
+        &mut self,
+        data: &'static WorldData,
+        room_row: usize,
+        room_col: usize,
+        effect: TransitionEffect,
+        frames: u16,
+    ) {
+        self.begin_change(Destination::World(data, room_row, room_col), effect, frames);
+    }
+
+    pub fn changing(&self) -> bool {
+        self.pending.is_some() || unsafe { Driver::instance_mut() }.video().transition_running()
+    }
+
+    fn begin_change(&mut self, destination: Destination, effect: TransitionEffect, frames: u16) {
+        let renderer = unsafe { Driver::instance_mut() }.video();
+        renderer.start_transition(effect, TransitionPhase::Out, frames);
+        self.pending = Some(PendingChange { destination, effect, frames });
+    }
+
+    /// once the old room's out of sight, swap in the new one and bring it in
+    fn finish_change(&mut self) {
+        let renderer = unsafe { Driver::instance_mut() }.video();
+        if !renderer.transition_hidden() {
+            return;
+        }
+        if let Some(change) = self.pending.take() {
+            match change.destination {
+                Destination::Room(row, col) => self.draw_room(row, col),
+                Destination::World(data, row, col) => {
+                    self.load_world(data);
+                    self.draw_room(row, col);
+                }
             }
+            renderer.start_transition(change.effect, TransitionPhase::In, change.frames);
         }
     }
 
+    pub fn camera_position(&self) -> (i32, i32) {
+        self.camera.position()
+    }
+
+    /// moves the view's top-left to (x, y) in pixels, kept within the world's edges.
+    /// the newly visible tiles get drawn on the next `advance_frame`.
+    pub fn set_camera(&mut self, x: i32, y: i32) {
+        if self.world_data.is_none() {
+            return;
+        }
+        let (cols, rows) = self.dimensions();
+        let max_x = (cols * ROOM_TILES * 8) as i32 - SCREEN_WIDTH;
+        let max_y = (rows * ROOM_TILES * 8) as i32 - SCREEN_HEIGHT;
+        self.camera.set_position(x.min(max_x).max(0), y.min(max_y).max(0));
+    }
+
+    pub fn scroll_camera(&mut self, dx: i32, dy: i32) {
+        let (x, y) = self.camera.position();
+        self.set_camera(x + dx, y + dy);
+    }
+
+    fn update_camera(&mut self) {
+        if let Some(data) = self.world_data {
+            self.camera.stream(&[
+                (data.bg_layer.as_ref(), ScreenblockAddress::Background as usize),
+                (data.fg_layer.as_ref(), ScreenblockAddress::Foreground as usize),
+            ]);
+        }
+        let (hofs, vofs) = self.camera.scroll_offsets();
+        BG1HOFS.write(BG_HOFS_BASE.wrapping_add(hofs));
+        BG1VOFS.write(BG_VOFS_BASE.wrapping_add(vofs));
+        BG3HOFS.write(BG_HOFS_BASE.wrapping_add(hofs));
+        BG3VOFS.write(BG_VOFS_BASE.wrapping_add(vofs));
+    }
+
+    /// in rooms, from whichever of the bg & fg layers the world has.  (0, 0) if neither.
     pub fn dimensions(&self) -> (usize, usize) {
-        let meta = &self
+        let layer = self
             .world_data
             .as_ref()
-            .expect("gfx")
-            .bg_layer
-            .as_ref()
-            .expect("bg_layer")
-            .meta;
+            .and_then(|data| data.bg_layer.as_ref().or_else(|| data.fg_layer.as_ref()));
+        let meta = match layer {
+            Some(layer) => &layer.meta,
+            None => return (0, 0),
+        };
         let rows = meta.0.len();
         let cols = meta.0.get(0).map(|x| x.len()).unwrap_or_default();
         (cols, rows)
@@ -386,72 +466,80 @@ impl World {
 
     pub fn advance_frame(&mut self) {
         self.frame_count += 1;
-        match self.skybox_anim {
-            SkyboxBehavior::None => {
-                BG2HOFS.write(BG_HOFS_BASE);
-                BG2VOFS.write(BG_VOFS_BASE);
-            }
-            SkyboxBehavior::Windmill => {
-                let angle = (self.frame_count << 7) as u16;
-                let params = BgAffineSetParams {
-                    data_center_x: 64 << 8,
-                    data_center_y: 64 << 8,
-                    display_center_x: 120,
-                    display_center_y: 80,
-                    scale_x: 0b10010000,
-                    scale_y: 0b10010000,
-                    angle,
-                };
-                gba::bios::bg_affine_set(&params, 0x400_0020usize, 1);
-
-                let x = (512 - (self.frame_count & 1023)).abs() as u16;
-                BG1HOFS.write(x);
-
-                let alpha = (32 - ((self.frame_count >> 3) & 63)).abs() as u16;
-                let renderer = unsafe { Driver::instance_mut().video() };
-                // HACK: don't alternate meshes on frames where we're swapping dominant layers
-                if self.frame_count & 511 == 256+127 {
-                    renderer.frame_counter -= 1;
-                }
-                if self.frame_count & 511 == 135 {
-                    renderer.frame_counter += 1;
-                }
-                // when bg2 (affine) is not fully opaque
-                if alpha != 32 {
-                    if alpha < 16 {
-                        gba::io::window::WINOUT.write(OutsideWindowSetting::new()
-                            .with_outside_bg0(true)
-                            .with_outside_bg1(true)
-                            .with_outside_bg2(true)
-                            .with_outside_bg3(true)
-                            .with_outside_color_special(true)
-                            .with_obj_win_bg0(true)
-                            .with_obj_win_bg1(true)
-                            .with_obj_win_bg2(false)
-                            .with_obj_win_bg3(true)
-                            .with_obj_win_color_special(true)
-                        );
-                    } else {
-                        gba::io::window::WINOUT.write(OutsideWindowSetting::new()
-                            .with_outside_bg0(true)
-                            .with_outside_bg1(true)
-                            .with_outside_bg2(true)
-                            .with_outside_bg3(true)
-                            .with_outside_color_special(true)
-                            .with_obj_win_bg0(true)
-                            .with_obj_win_bg1(false)
-                            .with_obj_win_bg2(true)
-                            .with_obj_win_bg3(true)
-                            .with_obj_win_color_special(true)
-                        );
-                    }
-                    BLDALPHA.write(
-                        AlphaBlendingSetting::new()
-                            .with_eva_coefficient(16 - (alpha & 15))
-                            .with_evb_coefficient(alpha & 15),
-                    );
-                }
+        if self.pending.is_some() {
+            self.finish_change();
+        }
+        self.update_camera();
+        self.animate_skybox();
+    }
+
+    fn animate_skybox(&mut self) {
+        let anim = self.world_data.map_or(&SkyboxAnimation::NONE, |data| &data.skybox_anim);
+        let frame = self.frame_count;
+
+        if let Some(affine) = &anim.affine {
+            let scale = affine.scale.at(frame) as i16;
+            let params = BgAffineSetParams {
+                data_center_x: affine.data_center.0 << 8,
+                data_center_y: affine.data_center.1 << 8,
+                display_center_x: affine.display_center.0,
+                display_center_y: affine.display_center.1,
+                scale_x: scale,
+                scale_y: scale,
+                angle: affine.angle.at(frame) as u16,
+            };
+            gba::bios::bg_affine_set(&params, 0x400_0020usize, 1);
+        } else if !anim.scroll.iter().any(|path| path.bg == 2) {
+            BG2HOFS.write(BG_HOFS_BASE);
+            BG2VOFS.write(BG_VOFS_BASE);
+        }
+
+        let (cam_x, cam_y) = self.camera.scroll_offsets();
+        for path in anim.scroll {
+            let mut x = BG_HOFS_BASE.wrapping_add(path.x.at(frame) as u16);
+            let mut y = BG_VOFS_BASE.wrapping_add(path.y.at(frame) as u16);
+            if let Some(shift) = path.camera_shift {
+                x = x.wrapping_add(cam_x >> shift);
+                y = y.wrapping_add(cam_y >> shift);
             }
+            let (hofs, vofs) = match path.bg {
+                0 => (BG0HOFS, BG0VOFS),
+                1 => (BG1HOFS, BG1VOFS),
+                2 => (BG2HOFS, BG2VOFS),
+                _ => (BG3HOFS, BG3VOFS),
+            };
+            hofs.write(x);
+            vofs.write(y);
+        }
+
+        if let Some(alpha) = &anim.alpha {
+            self.crossfade_skybox(alpha);
+        }
+    }
+
+    fn crossfade_skybox(&self, curve: &Curve) {
+        let alpha = curve.at(self.frame_count) as u16;
+        let next_alpha = curve.at(self.frame_count + 1) as u16;
+        let renderer = unsafe { Driver::instance_mut().video() };
+        // HACK: don't alternate meshes on frames where we're swapping dominant layers
+        if alpha >= 16 && next_alpha < 16 {
+            renderer.frame_counter += 1;
+        } else if alpha < 16 && next_alpha >= 16 {
+            renderer.frame_counter -= 1;
+        }
+        // when bg2 (affine) is not fully opaque
+        if alpha != 32 {
+            // only the more opaque of the two gets drawn in the mesh
+            renderer.windows().set_obj_window(WindowLayers {
+                bg1: alpha < 16,
+                bg2: alpha >= 16,
+                ..WindowLayers::ALL
+            });
+            BLDALPHA.write(
+                AlphaBlendingSetting::new()
+                    .with_eva_coefficient(16 - (alpha & 15))
+                    .with_evb_coefficient(alpha & 15),
+            );
         }
     }
 }